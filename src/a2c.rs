@@ -0,0 +1,290 @@
+use ndarray::Array1;
+use rand::SeedableRng;
+use rand_distr::{Distribution, weighted::WeightedIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::{game, model, reinforce};
+
+// Hyperparameters of train, the a2c table of an experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct A2cConfig {
+    // games stepped in lockstep
+    pub envs: usize,
+    pub updates: u16,
+    // steps every game takes between updates
+    pub n_steps: usize,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    pub value_loss_weight: f32,
+    // weight of the entropy bonus that keeps the policy exploring
+    pub entropy_weight: f32,
+}
+
+impl Default for A2cConfig {
+    fn default() -> Self {
+        Self {
+            envs: 8,
+            updates: 200,
+            n_steps: 5,
+            gamma: 0.99,
+            learning_rate: 0.0007,
+            value_loss_weight: 0.5,
+            entropy_weight: 0.01,
+        }
+    }
+}
+
+impl A2cConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.envs == 0 || self.updates == 0 || self.n_steps == 0 {
+            return Err("envs, updates and n_steps must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.gamma) {
+            return Err(format!("gamma must be in [0, 1], got {}", self.gamma));
+        }
+        if !(self.learning_rate > 0. && self.learning_rate.is_finite()) {
+            return Err(format!("learning_rate must be positive, got {}", self.learning_rate));
+        }
+        if !(self.value_loss_weight >= 0. && self.entropy_weight >= 0.) {
+            return Err("value_loss_weight and entropy_weight must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The actor outputs one logit per action and the critic a single state value.
+// With a shared trunk both heads read the trunk's output and their input
+// derivatives are summed back through it.
+#[derive(Clone)]
+pub enum ActorCritic {
+    Separate {
+        actor: model::Model,
+        critic: model::Model,
+    },
+    Shared {
+        trunk: model::Model,
+        actor_head: model::Model,
+        critic_head: model::Model,
+    },
+}
+
+impl ActorCritic {
+    // (action logits, state value)
+    pub fn evaluate(&mut self, state: &Array1<f32>) -> (Array1<f32>, f32) {
+        match self {
+            ActorCritic::Separate { actor, critic } => {
+                (actor.forward(state).clone(), critic.forward(state)[0])
+            }
+            ActorCritic::Shared {
+                trunk,
+                actor_head,
+                critic_head,
+            } => {
+                let features: &Array1<f32> = trunk.forward(state);
+                (
+                    actor_head.forward(features).clone(),
+                    critic_head.forward(features)[0],
+                )
+            }
+        }
+    }
+
+    // Must follow an evaluate() of the same state.
+    pub fn backprop(&mut self, state: &Array1<f32>, logit_derivative: &Array1<f32>, value_derivative: f32) {
+        let value_derivative: Array1<f32> = Array1::from_elem(1, value_derivative);
+        match self {
+            ActorCritic::Separate { actor, critic } => {
+                actor.backprop(state, logit_derivative);
+                critic.backprop(state, &value_derivative);
+            }
+            ActorCritic::Shared {
+                trunk,
+                actor_head,
+                critic_head,
+            } => {
+                let features: Array1<f32> = trunk.output().clone();
+                actor_head.backprop(&features, logit_derivative);
+                critic_head.backprop(&features, &value_derivative);
+                let trunk_derivative: Array1<f32> =
+                    actor_head.input_derivative() + critic_head.input_derivative();
+                trunk.backprop(state, &trunk_derivative);
+            }
+        }
+    }
+
+    pub fn apply_gradients(&mut self, learning_rate: f32) {
+        match self {
+            ActorCritic::Separate { actor, critic } => {
+                actor.apply_gradients(learning_rate);
+                critic.apply_gradients(learning_rate);
+            }
+            ActorCritic::Shared {
+                trunk,
+                actor_head,
+                critic_head,
+            } => {
+                trunk.apply_gradients(learning_rate);
+                actor_head.apply_gradients(learning_rate);
+                critic_head.apply_gradients(learning_rate);
+            }
+        }
+    }
+
+    pub fn greedy_action(&mut self, state: &Array1<f32>) -> usize {
+        model::argmax(&self.evaluate(state).0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpdateStats {
+    pub policy_loss: f32,
+    pub value_loss: f32,
+    pub entropy: f32,
+}
+
+struct Transition {
+    state: Array1<f32>,
+    action: usize,
+    reward: f32,
+    done: bool,
+}
+
+// Synchronous advantage actor-critic: every game advances n_steps in lockstep,
+// then one update is made from the n-step bootstrapped returns of all of them.
+pub fn train(games: &mut [game::Game], agent: &mut ActorCritic, config: &A2cConfig, seed: u64) -> Vec<UpdateStats> {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut state: Array1<f32> = Array1::zeros(games[0].observation_space);
+    let mut scores: Vec<f32> = vec![0.; games.len()];
+    let mut history: Vec<UpdateStats> = vec![];
+    for update in 0..config.updates {
+        let mut stats = UpdateStats::default();
+        let mut samples: usize = 0;
+        for (game, score) in games.iter_mut().zip(scores.iter_mut()) {
+            let mut rollout: Vec<Transition> = vec![];
+            for _ in 0..config.n_steps {
+                game.observe(&mut state);
+                let (logits, _) = agent.evaluate(&state);
+                let probabilities: Array1<f32> = model::softmax(&logits);
+                let choice: usize = WeightedIndex::new(probabilities.iter()).unwrap().sample(&mut rng);
+                let (reward, finished) = game.step(choice);
+                rollout.push(Transition {
+                    state: state.clone(),
+                    action: choice,
+                    reward,
+                    done: finished,
+                });
+                *score += reward;
+                if finished {
+                    println!("Scored: {}", score);
+                    *score = 0.;
+                    game.reset();
+                    break;
+                }
+            }
+            let mut running: f32 = if rollout.last().unwrap().done {
+                0.
+            } else {
+                game.observe(&mut state);
+                agent.evaluate(&state).1
+            };
+            for transition in rollout.iter().rev() {
+                running = transition.reward + config.gamma * running;
+                let (logits, value) = agent.evaluate(&transition.state);
+                let probabilities: Array1<f32> = model::softmax(&logits);
+                let entropy: f32 = model::entropy(&probabilities);
+                let advantage: f32 = running - value;
+                // dH/dlogits = -p * (log p + H); the entropy bonus is subtracted from the loss
+                let entropy_derivative: Array1<f32> =
+                    probabilities.mapv(|p| -p * (p.max(f32::EPSILON).ln() + entropy));
+                let logit_derivative: Array1<f32> =
+                    reinforce::policy_gradient(&probabilities, transition.action, advantage)
+                        - entropy_derivative * config.entropy_weight;
+                agent.backprop(
+                    &transition.state,
+                    &logit_derivative,
+                    config.value_loss_weight * (value - running),
+                );
+                stats.policy_loss -= probabilities[transition.action].max(f32::EPSILON).ln() * advantage;
+                stats.value_loss += 0.5 * (value - running).powi(2);
+                stats.entropy += entropy;
+                samples += 1;
+            }
+        }
+        agent.apply_gradients(config.learning_rate / samples as f32);
+        stats.policy_loss /= samples as f32;
+        stats.value_loss /= samples as f32;
+        stats.entropy /= samples as f32;
+        println!(
+            "Update {}\tPolicy loss: {}\tValue loss: {}\tEntropy: {}",
+            update, stats.policy_loss, stats.value_loss, stats.entropy
+        );
+        history.push(stats);
+    }
+    history
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::SEED;
+
+    fn shared(inputs: usize, actions: usize) -> ActorCritic {
+        let mut trunk = model::Model::with_seed(SEED);
+        trunk.add_layer(inputs, 8, true);
+        let mut actor_head = model::Model::with_seed(SEED + 1);
+        actor_head.add_layer(8, actions, false);
+        let mut critic_head = model::Model::with_seed(SEED + 2);
+        critic_head.add_layer(8, 1, false);
+        ActorCritic::Shared {
+            trunk,
+            actor_head,
+            critic_head,
+        }
+    }
+
+    fn trunk_parameters(agent: &ActorCritic) -> Array1<f32> {
+        match agent {
+            ActorCritic::Shared { trunk, .. } => trunk.flat_parameters(),
+            ActorCritic::Separate { .. } => unreachable!(),
+        }
+    }
+
+    // either head alone must move the shared trunk
+    #[test]
+    fn both_heads_train_the_shared_trunk() {
+        let state: Array1<f32> = Array1::from(vec![0.5, -0.25, 1.]);
+        for (logit_derivative, value_derivative) in [(Array1::from(vec![0.3, -0.3]), 0.), (Array1::zeros(2), 0.7)] {
+            let mut agent: ActorCritic = shared(3, 2);
+            let initial: Array1<f32> = trunk_parameters(&agent);
+            agent.evaluate(&state);
+            agent.backprop(&state, &logit_derivative, value_derivative);
+            agent.apply_gradients(0.1);
+            assert_ne!(trunk_parameters(&agent), initial);
+        }
+    }
+
+    #[test]
+    fn training_reports_every_update() {
+        let mut games: Vec<game::Game> = (0..2)
+            .map(|i| {
+                let mut game = game::Game::with_seed(SEED + i);
+                game.max_steps = 4;
+                game
+            })
+            .collect();
+        let mut agent: ActorCritic = shared(games[0].observation_space, games[0].action_space);
+        let config = A2cConfig {
+            updates: 3,
+            n_steps: 2,
+            ..Default::default()
+        };
+        let history: Vec<UpdateStats> = train(&mut games, &mut agent, &config, SEED);
+        assert_eq!(history.len(), 3);
+        let max_entropy: f32 = (games[0].action_space as f32).ln();
+        for stats in history {
+            assert!(stats.policy_loss.is_finite() && stats.value_loss >= 0.);
+            assert!(stats.entropy > 0. && stats.entropy <= max_entropy + 1e-5);
+        }
+    }
+}
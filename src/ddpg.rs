@@ -0,0 +1,312 @@
+use ndarray::{Array1, concatenate, s};
+use ndarray_rand::RandomExt;
+use rand::SeedableRng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use crate::train::{BATCH_SIZE, ContinuousExperience, ReplayBuffer};
+use crate::{game, model};
+
+// Hyperparameters of train for both variants, the ddpg table of an
+// experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DdpgConfig {
+    pub sessions: u16,
+    // uniformly random actions before the first update
+    pub warmup_steps: usize,
+    pub batch_size: usize,
+    pub gamma: f32,
+    // soft target update rate
+    pub tau: f32,
+    pub actor_learning_rate: f32,
+    pub critic_learning_rate: f32,
+    // std of the Gaussian noise added to the actor's actions
+    pub exploration_noise: f32,
+    // TD3 target policy smoothing and delayed actor updates
+    pub target_noise: f32,
+    pub target_noise_clip: f32,
+    pub policy_delay: usize,
+}
+
+impl Default for DdpgConfig {
+    fn default() -> Self {
+        Self {
+            sessions: 200,
+            warmup_steps: 50,
+            batch_size: BATCH_SIZE,
+            gamma: 0.99,
+            tau: 0.005,
+            actor_learning_rate: 0.0001,
+            critic_learning_rate: 0.001,
+            exploration_noise: 0.1,
+            target_noise: 0.2,
+            target_noise_clip: 0.5,
+            policy_delay: 2,
+        }
+    }
+}
+
+impl DdpgConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sessions == 0 || self.batch_size == 0 || self.policy_delay == 0 {
+            return Err("sessions, batch_size and policy_delay must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.gamma) {
+            return Err(format!("gamma must be in [0, 1], got {}", self.gamma));
+        }
+        if !(self.tau > 0. && self.tau <= 1.) {
+            return Err(format!("tau must be in (0, 1], got {}", self.tau));
+        }
+        if !(self.actor_learning_rate > 0. && self.critic_learning_rate > 0.) {
+            return Err("actor_learning_rate and critic_learning_rate must be positive".to_string());
+        }
+        if !(self.exploration_noise >= 0. && self.target_noise >= 0. && self.target_noise_clip >= 0.) {
+            return Err("exploration_noise, target_noise and target_noise_clip must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    Ddpg,
+    // twin critics, target policy smoothing, delayed actor updates
+    Td3,
+}
+
+// The actor's linear outputs are squashed with tanh into [-1, 1]. Critics take
+// the state and action concatenated and output a single Q-value.
+#[derive(Clone)]
+pub struct DeterministicActorCritic {
+    pub actor: model::Model,
+    pub actor_target: model::Model,
+    pub critics: Vec<model::Model>,
+    pub critic_targets: Vec<model::Model>,
+    pub variant: Variant,
+}
+
+impl DeterministicActorCritic {
+    // TD3 needs two differently initialised critics, DDPG uses only the first.
+    pub fn new(actor: model::Model, critics: Vec<model::Model>, variant: Variant) -> Self {
+        let critics: Vec<model::Model> = match variant {
+            Variant::Ddpg => critics.into_iter().take(1).collect(),
+            Variant::Td3 => {
+                assert_eq!(critics.len(), 2, "TD3 needs two critics");
+                critics
+            }
+        };
+        Self {
+            actor_target: actor.clone(),
+            actor,
+            critic_targets: critics.clone(),
+            critics,
+            variant,
+        }
+    }
+
+    pub fn act(&mut self, state: &Array1<f32>) -> Array1<f32> {
+        self.actor.forward(state).mapv(f32::tanh)
+    }
+
+    fn target_action(&mut self, state: &Array1<f32>, config: &DdpgConfig, rng: &mut rand::rngs::StdRng) -> Array1<f32> {
+        let action: Array1<f32> = self.actor_target.forward(state).mapv(f32::tanh);
+        match self.variant {
+            Variant::Ddpg => action,
+            Variant::Td3 => {
+                let noise: Array1<f32> =
+                    Array1::random_using(action.len(), Normal::new(0., config.target_noise).unwrap(), rng)
+                        .mapv(|n| n.clamp(-config.target_noise_clip, config.target_noise_clip));
+                (action + noise).mapv(|a| a.clamp(-1., 1.))
+            }
+        }
+    }
+
+    fn bellman_target(
+        &mut self,
+        experience: &ContinuousExperience,
+        config: &DdpgConfig,
+        rng: &mut rand::rngs::StdRng,
+    ) -> f32 {
+        if experience.done {
+            return experience.reward;
+        }
+        let next_action: Array1<f32> = self.target_action(&experience.next_state, config, rng);
+        let input: Array1<f32> = concatenate![ndarray::Axis(0), experience.next_state, next_action];
+        // TD3 takes the minimum of the twin target critics to curb overestimation
+        let next_value: f32 = self
+            .critic_targets
+            .iter_mut()
+            .map(|critic| critic.forward(&input)[0])
+            .fold(f32::INFINITY, f32::min);
+        experience.reward + config.gamma * next_value
+    }
+
+    // One critic step on the batch, returning the mean TD loss.
+    fn update_critics(
+        &mut self,
+        batch: &[&ContinuousExperience],
+        config: &DdpgConfig,
+        rng: &mut rand::rngs::StdRng,
+    ) -> f32 {
+        let mut loss: f32 = 0.;
+        for experience in batch {
+            let target: f32 = self.bellman_target(experience, config, rng);
+            let input: Array1<f32> = concatenate![ndarray::Axis(0), experience.state, experience.action];
+            for critic in &mut self.critics {
+                let error: f32 = critic.forward(&input)[0] - target;
+                loss += 0.5 * error * error;
+                critic.backprop(&input, &Array1::from_elem(1, error));
+            }
+        }
+        for critic in &mut self.critics {
+            critic.apply_gradients(config.critic_learning_rate / batch.len() as f32);
+        }
+        loss / (batch.len() * self.critics.len()) as f32
+    }
+
+    // Deterministic policy gradient through the first critic, returning the mean -Q.
+    fn update_actor(&mut self, batch: &[&ContinuousExperience], config: &DdpgConfig) -> f32 {
+        let mut loss: f32 = 0.;
+        let state_size: usize = batch[0].state.len();
+        for experience in batch {
+            let action: Array1<f32> = self.act(&experience.state);
+            let input: Array1<f32> = concatenate![ndarray::Axis(0), experience.state, action];
+            let critic: &mut model::Model = &mut self.critics[0];
+            loss -= critic.forward(&input)[0];
+            critic.backprop(&input, &Array1::from_elem(1, -1.));
+            let action_derivative: Array1<f32> = critic.input_derivative().slice(s![state_size..]).to_owned();
+            // chain through tanh: da/dz = 1 - a^2
+            self.actor
+                .backprop(&experience.state, &(action_derivative * action.mapv(|a| 1. - a * a)));
+        }
+        // the critic was only differentiated for its input derivative
+        self.critics[0].zero_gradients();
+        self.actor.apply_gradients(config.actor_learning_rate / batch.len() as f32);
+        loss / batch.len() as f32
+    }
+
+    fn update_targets(&mut self, tau: f32) {
+        self.actor_target.soft_update(&self.actor, tau);
+        for (target, critic) in self.critic_targets.iter_mut().zip(self.critics.iter()) {
+            target.soft_update(critic, tau);
+        }
+    }
+}
+
+// Off-policy loop on the continuous lander. Returns the score of every session.
+pub fn train(game: &mut game::Game, agent: &mut DeterministicActorCritic, config: &DdpgConfig, seed: u64) -> Vec<f32> {
+    let mut replay_buffer: ReplayBuffer<ContinuousExperience> = ReplayBuffer::new(seed);
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut state: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut steps: usize = 0;
+    let mut critic_updates: usize = 0;
+    let mut scores: Vec<f32> = vec![];
+    for _ in 0..config.sessions {
+        let mut score: f32 = 0.;
+        let mut critic_loss: f32 = 0.;
+        let mut actor_loss: f32 = 0.;
+        loop {
+            game.observe(&mut state);
+            let acted_upon_state: Array1<f32> = state.clone();
+            let action: Array1<f32> = if steps < config.warmup_steps {
+                Array1::random_using(game.continuous_action_space, rand_distr::Uniform::new(-1., 1.).unwrap(), &mut rng)
+            } else {
+                let noise: Array1<f32> = Array1::random_using(
+                    game.continuous_action_space,
+                    Normal::new(0., config.exploration_noise).unwrap(),
+                    &mut rng,
+                );
+                (agent.act(&state) + noise).mapv(|a| a.clamp(-1., 1.))
+            };
+            let (reward, finished) = game.step_continuous(&action);
+            game.observe(&mut state);
+            replay_buffer.push_experience(ContinuousExperience {
+                state: acted_upon_state,
+                action,
+                reward,
+                next_state: state.clone(),
+                done: finished,
+            });
+            steps += 1;
+            if steps >= config.warmup_steps {
+                let batch: Vec<&ContinuousExperience> = replay_buffer.sample(config.batch_size);
+                critic_loss = agent.update_critics(&batch, config, &mut rng);
+                critic_updates += 1;
+                if agent.variant == Variant::Ddpg || critic_updates.is_multiple_of(config.policy_delay) {
+                    actor_loss = agent.update_actor(&batch, config);
+                    agent.update_targets(config.tau);
+                }
+            }
+            score += reward;
+            if finished {
+                game.reset();
+                println!("Scored: {}\tCritic loss: {}\tActor loss: {}", score, critic_loss, actor_loss);
+                break;
+            }
+        }
+        scores.push(score);
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::train::SEED;
+
+    fn network(inputs: usize, outputs: usize, seed: u64) -> model::Model {
+        let mut network = model::Model::with_seed(seed);
+        network.add_layer(inputs, outputs, false);
+        network
+    }
+
+    fn agent(variant: Variant) -> DeterministicActorCritic {
+        DeterministicActorCritic::new(
+            network(3, 2, SEED),
+            vec![network(5, 1, SEED + 1), network(5, 1, SEED + 2)],
+            variant,
+        )
+    }
+
+    #[test]
+    fn targets_move_a_tau_fraction_towards_the_online_networks() {
+        let mut agent: DeterministicActorCritic = agent(Variant::Td3);
+        let target: Array1<f32> = agent.actor_target.flat_parameters();
+        let online: Array1<f32> = &target + 1.;
+        agent.actor.set_flat_parameters(&online);
+        let critic_target: Array1<f32> = agent.critic_targets[1].flat_parameters();
+        agent.critics[1].set_flat_parameters(&(&critic_target - 2.));
+        agent.update_targets(0.25);
+        let moved: Array1<f32> = agent.actor_target.flat_parameters() - &target;
+        assert!(moved.iter().all(|step| (step - 0.25).abs() < 1e-5));
+        let moved: Array1<f32> = agent.critic_targets[1].flat_parameters() - &critic_target;
+        assert!(moved.iter().all(|step| (step + 0.5).abs() < 1e-5));
+        // tau 1 copies the online network
+        agent.update_targets(1.);
+        assert_eq!(agent.actor_target.flat_parameters(), online);
+    }
+
+    #[test]
+    fn only_td3_smooths_the_target_action_within_the_clip() {
+        let state: Array1<f32> = Array1::from(vec![0.2, -0.4, 0.6]);
+        let config = DdpgConfig::default();
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut ddpg: DeterministicActorCritic = agent(Variant::Ddpg);
+        assert_eq!(ddpg.critics.len(), 1);
+        assert_eq!(ddpg.target_action(&state, &config, &mut rng), ddpg.act(&state));
+        let mut td3: DeterministicActorCritic = agent(Variant::Td3);
+        let clean: Array1<f32> = td3.act(&state);
+        let mut smoothed: bool = false;
+        for _ in 0..20 {
+            let noisy: Array1<f32> = td3.target_action(&state, &config, &mut rng);
+            for (noisy, clean) in noisy.iter().zip(clean.iter()) {
+                assert!(noisy.abs() <= 1. && (noisy - clean).abs() <= config.target_noise_clip + 1e-6);
+                smoothed |= noisy != clean;
+            }
+        }
+        assert!(smoothed);
+    }
+}
@@ -0,0 +1,349 @@
+use ndarray::{Array1, Array2, Axis};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+use crate::train::{self, Experience, ReplayBuffer, TrainConfig};
+
+const HUBER_KAPPA: f32 = 1.0;
+
+// Shapes of both heads, the distributional table of an experiment file. The
+// rest of C51 and QR-DQN training is configured by the train table.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DistributionalConfig {
+    pub atoms: usize,
+    pub v_min: f32,
+    pub v_max: f32,
+    pub quantiles: usize,
+}
+
+impl Default for DistributionalConfig {
+    fn default() -> Self {
+        Self {
+            atoms: 51,
+            v_min: -10.,
+            v_max: 10.,
+            quantiles: 32,
+        }
+    }
+}
+
+impl DistributionalConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.atoms < 2 {
+            return Err(format!("atoms must be at least 2, got {}", self.atoms));
+        }
+        if !(self.v_min < self.v_max && self.v_min.is_finite() && self.v_max.is_finite()) {
+            return Err(format!("v_min must be below v_max, got {} and {}", self.v_min, self.v_max));
+        }
+        if self.quantiles == 0 {
+            return Err("quantiles must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn categorical(&self) -> Head {
+        Head::Categorical {
+            atoms: self.atoms,
+            v_min: self.v_min,
+            v_max: self.v_max,
+        }
+    }
+
+    pub fn quantile(&self) -> Head {
+        Head::Quantile {
+            quantiles: self.quantiles,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Head {
+    // C51: softmax over evenly spaced return atoms in [v_min, v_max]
+    Categorical { atoms: usize, v_min: f32, v_max: f32 },
+    // QR-DQN: learned return values at fixed quantile midpoints
+    Quantile { quantiles: usize },
+}
+
+impl Head {
+    pub fn outputs_per_action(&self) -> usize {
+        match *self {
+            Head::Categorical { atoms, .. } => atoms,
+            Head::Quantile { quantiles } => quantiles,
+        }
+    }
+}
+
+// Wraps a model whose output layer has action_space * outputs_per_action units,
+// laid out action-major.
+#[derive(Clone)]
+pub struct DistributionalAgent {
+    pub model: model::Model,
+    pub head: Head,
+    pub action_space: usize,
+}
+
+impl DistributionalAgent {
+    pub fn new(model: model::Model, head: Head, action_space: usize) -> Self {
+        assert_eq!(
+            model.output_size(),
+            action_space * head.outputs_per_action(),
+            "Output layer must have one unit per (action, atom) pair"
+        );
+        Self {
+            model,
+            head,
+            action_space,
+        }
+    }
+
+    // return values of the categorical atoms
+    pub fn support(&self) -> Array1<f32> {
+        match self.head {
+            Head::Categorical { atoms, v_min, v_max } => Array1::linspace(v_min, v_max, atoms),
+            Head::Quantile { .. } => panic!("Quantile heads have no fixed support"),
+        }
+    }
+
+    // midpoints (2i + 1) / 2N of the quantile fractions
+    pub fn quantile_fractions(&self) -> Array1<f32> {
+        let n: usize = self.head.outputs_per_action();
+        Array1::from_shape_fn(n, |i| (2 * i + 1) as f32 / (2 * n) as f32)
+    }
+
+    // actions x atoms: probabilities for C51, quantile values for QR-DQN
+    pub fn distribution(&mut self, state: &Array1<f32>) -> Array2<f32> {
+        let per_action: usize = self.head.outputs_per_action();
+        let raw: Array2<f32> = self
+            .model
+            .forward(state)
+            .clone()
+            .into_shape_with_order((self.action_space, per_action))
+            .unwrap();
+        match self.head {
+            Head::Categorical { .. } => {
+                let mut probabilities: Array2<f32> = raw;
+                for mut row in probabilities.rows_mut() {
+                    let normalised: Array1<f32> = model::softmax(&row.to_owned());
+                    row.assign(&normalised);
+                }
+                probabilities
+            }
+            Head::Quantile { .. } => raw,
+        }
+    }
+
+    fn expected_values(&self, distribution: &Array2<f32>) -> Array1<f32> {
+        match self.head {
+            Head::Categorical { .. } => distribution.dot(&self.support()),
+            Head::Quantile { .. } => distribution.mean_axis(Axis(1)).unwrap(),
+        }
+    }
+
+    pub fn q_values(&mut self, state: &Array1<f32>) -> Array1<f32> {
+        let distribution: Array2<f32> = self.distribution(state);
+        self.expected_values(&distribution)
+    }
+
+    // per-action standard deviation of the predicted return
+    pub fn return_std(&mut self, state: &Array1<f32>) -> Array1<f32> {
+        let distribution: Array2<f32> = self.distribution(state);
+        let means: Array1<f32> = self.expected_values(&distribution);
+        match self.head {
+            Head::Categorical { .. } => {
+                let support: Array1<f32> = self.support();
+                let second_moment: Array1<f32> = distribution.dot(&support.mapv(|z| z * z));
+                (second_moment - means.mapv(|m| m * m)).mapv(|v| v.max(0.).sqrt())
+            }
+            Head::Quantile { .. } => distribution.std_axis(Axis(1), 0.),
+        }
+    }
+
+    pub fn greedy_action(&mut self, state: &Array1<f32>) -> usize {
+        model::argmax(&self.q_values(state))
+    }
+
+    // Distributional Bellman target for one transition: the projected
+    // probabilities for C51, the shifted quantile values for QR-DQN.
+    fn target(&self, target_distribution: &Array2<f32>, experience: &Experience, gamma: f32) -> Array1<f32> {
+        let next_action: usize = model::argmax(&self.expected_values(target_distribution));
+        let next: Array1<f32> = target_distribution.row(next_action).to_owned();
+        match self.head {
+            Head::Categorical { atoms, v_min, v_max } => {
+                let delta_z: f32 = (v_max - v_min) / (atoms - 1) as f32;
+                let mut projected: Array1<f32> = Array1::zeros(atoms);
+                for (z, p) in self.support().iter().zip(next.iter()) {
+                    let shifted: f32 = if experience.done {
+                        experience.reward
+                    } else {
+                        experience.reward + gamma * z
+                    };
+                    // rounding can put a value clamped to v_max just past the last atom
+                    let b: f32 = ((shifted.clamp(v_min, v_max) - v_min) / delta_z).clamp(0., (atoms - 1) as f32);
+                    let (lower, upper) = (b.floor() as usize, b.ceil() as usize);
+                    if lower == upper {
+                        projected[lower] += p;
+                    } else {
+                        projected[lower] += p * (upper as f32 - b);
+                        projected[upper] += p * (b - lower as f32);
+                    }
+                }
+                projected
+            }
+            Head::Quantile { .. } => {
+                if experience.done {
+                    Array1::from_elem(next.len(), experience.reward)
+                } else {
+                    next.mapv(|theta| experience.reward + gamma * theta)
+                }
+            }
+        }
+    }
+
+    // Returns (loss, derivative w.r.t. the raw network outputs). Only the
+    // taken action's slice of the derivative is non-zero.
+    fn loss_derivative(
+        &mut self,
+        experience: &Experience,
+        target_distribution: &Array2<f32>,
+        gamma: f32,
+    ) -> (f32, Array1<f32>) {
+        let per_action: usize = self.head.outputs_per_action();
+        let target: Array1<f32> = self.target(target_distribution, experience, gamma);
+        let current: Array1<f32> = self.distribution(&experience.state).row(experience.action).to_owned();
+        let mut derivative: Array1<f32> = Array1::zeros(self.action_space * per_action);
+        let offset: usize = experience.action * per_action;
+        let loss: f32 = match self.head {
+            Head::Categorical { .. } => {
+                // cross-entropy against the projected target, d/dlogits = p - m
+                for i in 0..per_action {
+                    derivative[offset + i] = current[i] - target[i];
+                }
+                -target
+                    .iter()
+                    .zip(current.iter())
+                    .map(|(m, p)| m * p.max(f32::EPSILON).ln())
+                    .sum::<f32>()
+            }
+            Head::Quantile { .. } => {
+                // quantile Huber loss, summed over predicted and averaged over target quantiles
+                let taus: Array1<f32> = self.quantile_fractions();
+                let mut loss: f32 = 0.;
+                for i in 0..per_action {
+                    for j in 0..per_action {
+                        let u: f32 = target[j] - current[i];
+                        let weight: f32 = (taus[i] - if u < 0. { 1. } else { 0. }).abs();
+                        let huber: f32 = if u.abs() <= HUBER_KAPPA {
+                            0.5 * u * u
+                        } else {
+                            HUBER_KAPPA * (u.abs() - 0.5 * HUBER_KAPPA)
+                        };
+                        loss += weight * huber / HUBER_KAPPA / per_action as f32;
+                        derivative[offset + i] -= weight * u.clamp(-HUBER_KAPPA, HUBER_KAPPA)
+                            / HUBER_KAPPA
+                            / per_action as f32;
+                    }
+                }
+                loss
+            }
+        };
+        (loss, derivative)
+    }
+}
+
+// Same interaction loop and hyperparameters as train::train, with a
+// distributional loss. Returns the score of every session.
+pub fn train(game: &mut crate::game::Game, agent: &mut DistributionalAgent, config: &TrainConfig) -> Vec<f32> {
+    let config: &TrainConfig = config.validated();
+    let mut replay_buffer: ReplayBuffer<Experience> = ReplayBuffer::new(config.seed);
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(config.seed);
+    let mut target: DistributionalAgent = agent.clone();
+    let mut state: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut epsilon: f32 = if agent.model.is_noisy() { 0. } else { config.initial_epsilon };
+    let mut sample_progress: usize = 0;
+    let mut scores: Vec<f32> = vec![];
+    for iter in 0..config.sessions {
+        epsilon *= config.epsilon_decay;
+        let mut score: f32 = 0.;
+        let mut loss: f32 = 0.;
+        loop {
+            game.observe(&mut state);
+            let acted_upon_state: Array1<f32> = state.clone();
+            agent.model.resample_noise();
+            let choice: usize = if rng.random::<f32>() > epsilon {
+                agent.greedy_action(&state)
+            } else {
+                rng.random_range(0..game.action_space)
+            };
+            let (reward, finished) = game.step(choice);
+            game.observe(&mut state);
+            replay_buffer.push_experience(Experience {
+                state: acted_upon_state,
+                action: choice,
+                reward,
+                next_state: state.clone(),
+                done: finished,
+            });
+            sample_progress += 1;
+            if sample_progress.is_multiple_of(config.sampling_frequency) {
+                target.model.resample_noise();
+                for experience in replay_buffer.sample(config.batch_size) {
+                    let target_distribution: Array2<f32> = target.distribution(&experience.next_state);
+                    let (experience_loss, loss_derivative) =
+                        agent.loss_derivative(experience, &target_distribution, config.gamma);
+                    loss += experience_loss;
+                    agent.model.backprop(&experience.state, &loss_derivative);
+                }
+                agent.model.apply_gradients(config.learning_rate);
+                if sample_progress.is_multiple_of(config.sampling_frequency * config.target_update_frequency) {
+                    target = agent.clone();
+                }
+            }
+            score += reward;
+            if finished {
+                game.reset();
+                println!("Scored: {}\tLoss: {}", score, loss);
+                break;
+            }
+        }
+        scores.push(score);
+        if config.recording.is_due(iter as usize + 1) {
+            agent.model.set_training(false);
+            train::record_greedy_episode(
+                game,
+                |state| agent.greedy_action(state),
+                &config.recording,
+                iter + 1,
+            );
+            agent.model.set_training(true);
+        }
+    }
+    agent.model.set_training(false);
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projection_at_v_max_stays_on_the_support() {
+        // (14 / (14 / 23)) rounds to just above 23, past the last atom
+        let (atoms, v_min, v_max) = (24, -7., 7.);
+        let mut model = model::Model::new();
+        model.add_layer(2, 2 * atoms, false);
+        let agent = DistributionalAgent::new(model, Head::Categorical { atoms, v_min, v_max }, 2);
+        let experience = Experience {
+            state: Array1::zeros(2),
+            action: 0,
+            reward: v_max,
+            next_state: Array1::zeros(2),
+            done: false,
+        };
+        let target_distribution: Array2<f32> = Array2::from_elem((2, atoms), 1. / atoms as f32);
+        let projected: Array1<f32> = agent.target(&target_distribution, &experience, 0.99);
+        assert!((projected.sum() - 1.).abs() < 1e-5);
+        assert!(projected[atoms - 1] > 0.);
+    }
+}
@@ -0,0 +1,334 @@
+use ndarray::{Array1, Array2, Axis};
+use ndarray_rand::RandomExt;
+use rand::SeedableRng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use crate::experiment::EnvironmentConfig;
+use crate::{game, model};
+
+// Hyperparameters of cross_entropy_method, the cross_entropy table of an
+// experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrossEntropyConfig {
+    pub generations: u16,
+    // seeded episodes each candidate is scored on
+    pub evaluation_episodes: u64,
+    pub population: usize,
+    pub elite_fraction: f32,
+    pub initial_std: f32,
+    // extra variance added each generation so the search does not collapse early
+    pub extra_std: f32,
+}
+
+impl Default for CrossEntropyConfig {
+    fn default() -> Self {
+        Self {
+            generations: 50,
+            evaluation_episodes: 4,
+            population: 32,
+            elite_fraction: 0.2,
+            initial_std: 0.5,
+            extra_std: 0.01,
+        }
+    }
+}
+
+impl CrossEntropyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.generations == 0 || self.evaluation_episodes == 0 || self.population == 0 {
+            return Err("generations, evaluation_episodes and population must be positive".to_string());
+        }
+        if !(self.elite_fraction > 0. && self.elite_fraction <= 1.) {
+            return Err(format!("elite_fraction must be in (0, 1], got {}", self.elite_fraction));
+        }
+        if !(self.initial_std > 0. && self.extra_std >= 0.) {
+            return Err("initial_std must be positive and extra_std not negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Hyperparameters of evolution_strategies, the evolution_strategies table of
+// an experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvolutionStrategiesConfig {
+    pub generations: u16,
+    // seeded episodes each candidate is scored on
+    pub evaluation_episodes: u64,
+    // split into antithetic pairs, so it must be even
+    pub population: usize,
+    pub noise_std: f32,
+    pub learning_rate: f32,
+}
+
+impl Default for EvolutionStrategiesConfig {
+    fn default() -> Self {
+        Self {
+            generations: 50,
+            evaluation_episodes: 4,
+            population: 32,
+            noise_std: 0.05,
+            learning_rate: 0.01,
+        }
+    }
+}
+
+impl EvolutionStrategiesConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.generations == 0 || self.evaluation_episodes == 0 {
+            return Err("generations and evaluation_episodes must be positive".to_string());
+        }
+        if self.population < 2 || !self.population.is_multiple_of(2) {
+            return Err(format!("population must be even and at least 2, got {}", self.population));
+        }
+        if !(self.noise_std > 0. && self.noise_std.is_finite()) {
+            return Err(format!("noise_std must be positive, got {}", self.noise_std));
+        }
+        if !(self.learning_rate > 0. && self.learning_rate.is_finite()) {
+            return Err(format!("learning_rate must be positive, got {}", self.learning_rate));
+        }
+        Ok(())
+    }
+}
+
+// Mean score of the greedy policy over a fixed set of seeded episodes.
+pub fn evaluate(policy: &mut model::Model, environment: &EnvironmentConfig, seeds: &[u64]) -> f32 {
+    evaluate_with(|state| model::argmax(policy.forward(state)), environment, seeds)
+}
+
+// Mean score of any state -> action chooser over a fixed set of seeded
+// episodes of the environment. Its own seed is overridden, and fitness
+// episodes are never logged as there are thousands of them.
+pub fn evaluate_with(
+    mut choose: impl FnMut(&Array1<f32>) -> usize,
+    environment: &EnvironmentConfig,
+    seeds: &[u64],
+) -> f32 {
+    let mut total: f32 = 0.;
+    for &seed in seeds {
+        let mut game: game::Game = EnvironmentConfig {
+            seed: Some(seed),
+            trajectory_dir: None,
+            ..environment.clone()
+        }
+        .build(0);
+        let mut state: Array1<f32> = Array1::zeros(game.observation_space);
+        loop {
+            game.observe(&mut state);
+            let (reward, finished) = game.step(choose(&state));
+            total += reward;
+            if finished {
+                break;
+            }
+        }
+    }
+    total / seeds.len() as f32
+}
+
+fn evaluate_parameters(
+    policy: &mut model::Model,
+    parameters: &Array1<f32>,
+    environment: &EnvironmentConfig,
+    seeds: &[u64],
+) -> f32 {
+    policy.set_flat_parameters(parameters);
+    evaluate(policy, environment, seeds)
+}
+
+// every candidate of a generation is scored on the same episodes
+pub(crate) fn generation_seeds(seed: u64, generation: u16, episodes: u64) -> Vec<u64> {
+    (0..episodes).map(|i| seed + generation as u64 * episodes + i).collect()
+}
+
+// Centred ranks in [-0.5, 0.5], making the update invariant to the reward scale.
+pub fn centered_ranks(fitness: &[f32]) -> Array1<f32> {
+    let mut order: Vec<usize> = (0..fitness.len()).collect();
+    order.sort_by(|&a, &b| fitness[a].partial_cmp(&fitness[b]).unwrap());
+    let mut ranks: Array1<f32> = Array1::zeros(fitness.len());
+    for (rank, &i) in order.iter().enumerate() {
+        ranks[i] = rank as f32 / (fitness.len() - 1) as f32 - 0.5;
+    }
+    ranks
+}
+
+// Cross-entropy method over the flattened parameters: sample a Gaussian
+// population, refit the mean and std to the elite. Leaves the model at the
+// final mean and returns the mean's score each generation. The seed draws
+// the population and the fitness episodes.
+pub fn cross_entropy_method(
+    policy: &mut model::Model,
+    environment: &EnvironmentConfig,
+    config: &CrossEntropyConfig,
+    seed: u64,
+) -> Vec<f32> {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let dims: usize = policy.num_parameters();
+    let elite: usize = ((config.population as f32 * config.elite_fraction) as usize).max(1);
+    let mut mean: Array1<f32> = policy.flat_parameters();
+    let mut std: Array1<f32> = Array1::from_elem(dims, config.initial_std);
+    let mut history: Vec<f32> = vec![];
+    for generation in 0..config.generations {
+        let seeds: Vec<u64> = generation_seeds(seed, generation, config.evaluation_episodes);
+        let noise: Array2<f32> = Array2::random_using((config.population, dims), Normal::new(0., 1.).unwrap(), &mut rng);
+        let population: Array2<f32> = &noise * &std + &mean;
+        let fitness: Vec<f32> = population
+            .rows()
+            .into_iter()
+            .map(|candidate| evaluate_parameters(policy, &candidate.to_owned(), environment, &seeds))
+            .collect();
+        let mut order: Vec<usize> = (0..config.population).collect();
+        order.sort_by(|&a, &b| fitness[b].partial_cmp(&fitness[a]).unwrap());
+        let elites: Array2<f32> = population.select(Axis(0), &order[..elite]);
+        mean = elites.mean_axis(Axis(0)).unwrap();
+        std = elites.std_axis(Axis(0), 0.) + config.extra_std;
+        let score: f32 = evaluate_parameters(policy, &mean, environment, &seeds);
+        println!(
+            "Generation {}\tBest: {}\tMean policy: {}",
+            generation, fitness[order[0]], score
+        );
+        history.push(score);
+    }
+    policy.set_flat_parameters(&mean);
+    history
+}
+
+// OpenAI-style evolution strategies: antithetic Gaussian perturbations,
+// centred-rank fitness shaping and a gradient-ascent step on the parameters.
+pub fn evolution_strategies(
+    policy: &mut model::Model,
+    environment: &EnvironmentConfig,
+    config: &EvolutionStrategiesConfig,
+    seed: u64,
+) -> Vec<f32> {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let dims: usize = policy.num_parameters();
+    let pairs: usize = config.population / 2;
+    let mut parameters: Array1<f32> = policy.flat_parameters();
+    let mut history: Vec<f32> = vec![];
+    for generation in 0..config.generations {
+        let seeds: Vec<u64> = generation_seeds(seed, generation, config.evaluation_episodes);
+        let noise: Array2<f32> = Array2::random_using((pairs, dims), Normal::new(0., 1.).unwrap(), &mut rng);
+        let mut fitness: Vec<f32> = Vec::with_capacity(2 * pairs);
+        for epsilon in noise.rows() {
+            fitness.push(evaluate_parameters(policy, &(&parameters + &(&epsilon * config.noise_std)), environment, &seeds));
+            fitness.push(evaluate_parameters(policy, &(&parameters - &(&epsilon * config.noise_std)), environment, &seeds));
+        }
+        let ranks: Array1<f32> = centered_ranks(&fitness);
+        let mut gradient: Array1<f32> = Array1::zeros(dims);
+        for (i, epsilon) in noise.rows().into_iter().enumerate() {
+            // the antithetic pair contributes (F+ - F-) * epsilon
+            gradient.scaled_add(ranks[2 * i] - ranks[2 * i + 1], &epsilon);
+        }
+        gradient /= 2. * pairs as f32 * config.noise_std;
+        parameters.scaled_add(config.learning_rate, &gradient);
+        let score: f32 = evaluate_parameters(policy, &parameters, environment, &seeds);
+        println!(
+            "Generation {}\tBest: {}\tPolicy: {}",
+            generation,
+            fitness.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+            score
+        );
+        history.push(score);
+    }
+    policy.set_flat_parameters(&parameters);
+    history
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::PixelConfig;
+
+    #[test]
+    fn fitness_episodes_play_the_configured_environment() {
+        let environment = EnvironmentConfig {
+            max_steps: 5,
+            pixels: Some(PixelConfig {
+                width: 4,
+                height: 4,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut policy = model::Model::new();
+        policy.add_layer(environment.pixels.as_ref().unwrap().observation_size(), 2, false);
+        let mut steps: usize = 0;
+        let score: f32 = evaluate_with(
+            |state| {
+                steps += 1;
+                model::argmax(policy.forward(state))
+            },
+            &environment,
+            &[1, 2],
+        );
+        assert!(score.is_finite());
+        assert_eq!(steps, 10);
+    }
+
+    #[test]
+    fn centered_ranks_ignore_the_fitness_scale() {
+        assert_eq!(centered_ranks(&[3., 1., 2.]), Array1::from(vec![0.5, -0.5, 0.]));
+        assert_eq!(centered_ranks(&[300., -10., 20.]), centered_ranks(&[3., 1., 2.]));
+    }
+
+    #[test]
+    fn generations_are_scored_on_distinct_episodes() {
+        let first: Vec<u64> = generation_seeds(7, 0, 4);
+        let second: Vec<u64> = generation_seeds(7, 1, 4);
+        assert_eq!(first, vec![7, 8, 9, 10]);
+        assert!(second.iter().all(|seed| !first.contains(seed)));
+    }
+
+    #[test]
+    fn odd_evolution_strategies_populations_are_rejected() {
+        let config = EvolutionStrategiesConfig {
+            population: 7,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(EvolutionStrategiesConfig::default().validate().is_ok());
+    }
+
+    fn small_policy(environment: &EnvironmentConfig) -> model::Model {
+        let game: game::Game = environment.build(0);
+        let mut policy = model::Model::with_seed(1);
+        policy.add_layer(game.observation_space, game.action_space, false);
+        policy
+    }
+
+    #[test]
+    fn searches_follow_the_seed_and_report_every_generation() {
+        let environment = EnvironmentConfig {
+            max_steps: 3,
+            ..Default::default()
+        };
+        let cross_entropy = CrossEntropyConfig {
+            generations: 2,
+            evaluation_episodes: 1,
+            population: 4,
+            ..Default::default()
+        };
+        let strategies = EvolutionStrategiesConfig {
+            generations: 2,
+            evaluation_episodes: 1,
+            population: 4,
+            ..Default::default()
+        };
+        let run = |seed: u64| {
+            let mut cem: model::Model = small_policy(&environment);
+            let cem_history: Vec<f32> = cross_entropy_method(&mut cem, &environment, &cross_entropy, seed);
+            let mut es: model::Model = small_policy(&environment);
+            let es_history: Vec<f32> = evolution_strategies(&mut es, &environment, &strategies, seed);
+            assert_eq!((cem_history.len(), es_history.len()), (2, 2));
+            (cem.flat_parameters(), es.flat_parameters())
+        };
+        let (cem, es) = run(3);
+        assert_eq!(run(3), (cem.clone(), es.clone()));
+        // both searches leave the policy at their final parameters
+        assert_ne!(cem, small_policy(&environment).flat_parameters());
+        assert_ne!(es, small_policy(&environment).flat_parameters());
+    }
+}
@@ -0,0 +1,677 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::a2c::A2cConfig;
+use crate::ddpg::DdpgConfig;
+use crate::distributional::DistributionalConfig;
+use crate::evolution::{CrossEntropyConfig, EvolutionStrategiesConfig};
+use crate::imitation::BehaviorCloningConfig;
+use crate::neat::NeatConfig;
+use crate::offline::OfflineConfig;
+use crate::pixels::PixelConfig;
+use crate::ppo::PpoConfig;
+use crate::recording::RecordingConfig;
+use crate::reinforce::ReinforceConfig;
+use crate::sac::SacConfig;
+use crate::tabular::TabularConfig;
+use crate::trajectory::TrajectoryLog;
+use crate::train::{SEED, TrainConfig};
+use crate::{game, model};
+
+// name of the resolved config written into every run's output directory
+pub const RESOLVED_CONFIG: &str = "config.toml";
+// extension of the trained network's parameters, see checkpoint_path
+pub const CHECKPOINT_EXTENSION: &str = "bin";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    Dqn,
+    C51,
+    QrDqn,
+    Reinforce,
+    A2c,
+    Ppo,
+    PpoContinuous,
+    Ddpg,
+    Td3,
+    Sac,
+    QLearning,
+    Sarsa,
+    ExpectedSarsa,
+    CrossEntropy,
+    EvolutionStrategies,
+    Neat,
+    BehaviorCloning,
+    Dqfd,
+    Cql,
+    Bcq,
+}
+
+impl Algorithm {
+    // as written in config files
+    pub fn name(&self) -> String {
+        toml::Value::try_from(self).unwrap().as_str().unwrap().to_string()
+    }
+
+    // Whether training saves a checkpoint: only policies that are the argmax
+    // of a single network built from the network table are.
+    pub fn is_checkpointed(&self) -> bool {
+        matches!(
+            self,
+            Algorithm::Dqn
+                | Algorithm::Reinforce
+                | Algorithm::Ppo
+                | Algorithm::CrossEntropy
+                | Algorithm::EvolutionStrategies
+                | Algorithm::BehaviorCloning
+                | Algorithm::Dqfd
+                | Algorithm::Cql
+        )
+    }
+
+    // Tabular agents bin the state vector's position and velocity, and NEAT
+    // starts from a connection per input, so neither can use pixels.
+    pub fn needs_state_vector(&self) -> bool {
+        matches!(
+            self,
+            Algorithm::QLearning | Algorithm::Sarsa | Algorithm::ExpectedSarsa | Algorithm::Neat
+        )
+    }
+
+    // the hyperparameter tables training reads, see ExperimentConfig
+    pub fn tables(&self) -> &'static [&'static str] {
+        match self {
+            Algorithm::Dqn | Algorithm::Dqfd => &["train"],
+            Algorithm::C51 | Algorithm::QrDqn => &["train", "distributional"],
+            Algorithm::Reinforce => &["reinforce"],
+            Algorithm::A2c => &["a2c"],
+            Algorithm::Ppo | Algorithm::PpoContinuous => &["ppo"],
+            Algorithm::Ddpg | Algorithm::Td3 => &["ddpg"],
+            Algorithm::Sac => &["sac"],
+            Algorithm::QLearning | Algorithm::Sarsa | Algorithm::ExpectedSarsa => &["tabular"],
+            Algorithm::CrossEntropy => &["cross_entropy"],
+            Algorithm::EvolutionStrategies => &["evolution_strategies"],
+            Algorithm::Neat => &["neat"],
+            Algorithm::BehaviorCloning => &["behavior_cloning"],
+            Algorithm::Cql | Algorithm::Bcq => &["offline"],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvironmentConfig {
+    pub max_steps: u16,
+    // start states are drawn from this seed when set, unseeded otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    // stacked image observations when set, the state vector otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixels: Option<PixelConfig>,
+    // every finished episode is logged here when set, relative to output_dir
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trajectory_dir: Option<PathBuf>,
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        Self {
+            max_steps: *game::MAX_STEPS,
+            seed: None,
+            pixels: None,
+            trajectory_dir: None,
+        }
+    }
+}
+
+// Start time and process id of this run, the same for every game it builds,
+// so trajectories of different commands sharing a directory stay apart.
+fn run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| {
+        let started: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        format!("{}_{}", started, process::id())
+    })
+}
+
+impl EnvironmentConfig {
+    // Parallel environments pass their index so their seeds differ.
+    pub fn build(&self, index: u64) -> game::Game {
+        let mut game: game::Game = match self.seed {
+            Some(seed) => game::Game::with_seed(seed + index),
+            None => game::Game::new(),
+        };
+        game.max_steps = self.max_steps;
+        if let Some(pixels) = &self.pixels {
+            game.use_pixels(pixels.clone());
+        }
+        if let Some(directory) = &self.trajectory_dir {
+            game.log_trajectories(Some(TrajectoryLog::new(
+                directory,
+                &format!("run_{}_env_{}", run_id(), index),
+                self.config_hash(),
+            )));
+        }
+        game
+    }
+
+    // FNV-1a of the settings that change how episodes play out, so a
+    // trajectory is only replayed on a game that can reproduce it. The seed
+    // and trajectory_dir are left out: trajectories carry their own episode
+    // seed, and where they are written changes nothing.
+    pub fn config_hash(&self) -> u64 {
+        let simulated = Self {
+            seed: None,
+            trajectory_dir: None,
+            ..self.clone()
+        };
+        let description: String = toml::to_string(&simulated).unwrap();
+        description
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    pub pooling: model::Pooling,
+    pub size: usize,
+    pub stride: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            pooling: model::Pooling::Max,
+            size: 2,
+            stride: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConvConfig {
+    // output channels
+    pub channels: usize,
+    pub kernel: usize,
+    pub stride: usize,
+    pub padding: usize,
+    // after the convolution's ReLU, none when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolConfig>,
+}
+
+impl Default for ConvConfig {
+    fn default() -> Self {
+        Self {
+            channels: 16,
+            kernel: 3,
+            stride: 1,
+            padding: 0,
+            pool: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    // sizes of the ReLU layers between input and output
+    pub hidden_layers: Vec<usize>,
+    // factorised Gaussian noisy layers, exploring without epsilon
+    pub noisy: bool,
+    // ReLU convolutions over pixel observations, before the hidden layers
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conv: Vec<ConvConfig>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            hidden_layers: vec![512, 256, 64],
+            noisy: false,
+            conv: vec![],
+        }
+    }
+}
+
+impl NetworkConfig {
+    // Convolutions need pixel observations, and every kernel and pooling
+    // window has to fit the image it slides over.
+    pub fn validate(&self, pixels: Option<&PixelConfig>) -> Result<(), String> {
+        if self.hidden_layers.contains(&0) {
+            return Err("hidden layer sizes must be positive".to_string());
+        }
+        if self.conv.is_empty() {
+            return Ok(());
+        }
+        let Some(pixels) = pixels else {
+            return Err("convolutions need pixel observations".to_string());
+        };
+        let mut shape: model::ImageShape = pixels.shape();
+        for (i, conv) in self.conv.iter().enumerate() {
+            if conv.channels == 0 {
+                return Err(format!("convolution {} has no channels", i));
+            }
+            let (height, width) = shape
+                .window_output(conv.kernel, conv.stride, conv.padding)
+                .ok_or_else(|| format!("convolution {} does not fit its {}x{} input", i, shape.height, shape.width))?;
+            shape = model::ImageShape::new(conv.channels, height, width);
+            if let Some(pool) = &conv.pool {
+                let (height, width) = shape
+                    .window_output(pool.size, pool.stride, 0)
+                    .ok_or_else(|| format!("pooling of convolution {} does not fit its {}x{} input", i, height, width))?;
+                shape = model::ImageShape::new(conv.channels, height, width);
+            }
+        }
+        Ok(())
+    }
+
+    // Linear layers only, the conv list is ignored.
+    pub fn build(&self, inputs: usize, outputs: usize, seed: u64) -> model::Model {
+        let mut network: model::Model = model::Model::with_seed(seed);
+        self.add_linear_layers(&mut network, inputs, outputs);
+        network
+    }
+
+    // The convolutions, each with its pooling, then the layers of build.
+    pub fn build_image(&self, input: model::ImageShape, outputs: usize, seed: u64) -> model::Model {
+        let mut network: model::Model = model::Model::with_seed(seed);
+        let mut shape: model::ImageShape = input;
+        for conv in &self.conv {
+            shape = network.add_conv_layer(shape, conv.channels, conv.kernel, conv.stride, conv.padding, true);
+            if let Some(pool) = &conv.pool {
+                shape = network.add_pool_layer(shape, pool.pooling, pool.size, pool.stride);
+            }
+        }
+        let inputs: usize = network.add_flatten_layer(shape);
+        self.add_linear_layers(&mut network, inputs, outputs);
+        network
+    }
+
+    fn add_linear_layers(&self, network: &mut model::Model, inputs: usize, outputs: usize) {
+        let mut sizes: Vec<usize> = vec![inputs];
+        sizes.extend(&self.hidden_layers);
+        sizes.push(outputs);
+        for (i, pair) in sizes.windows(2).enumerate() {
+            // every layer but the output one is followed by a ReLU
+            let relu: bool = i + 2 < sizes.len();
+            if self.noisy {
+                network.add_noisy_layer(pair[0], pair[1], relu);
+            } else {
+                network.add_layer(pair[0], pair[1], relu);
+            }
+        }
+    }
+}
+
+// A whole experiment, loadable from TOML or JSON. Missing fields take their
+// defaults, unknown ones are rejected. The train table configures the DQN
+// family (Dqn, Dqfd, C51, QrDqn) and every other algorithm has a table of its
+// own, see Algorithm::tables; configs that change a table the algorithm does
+// not read are rejected.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExperimentConfig {
+    pub name: String,
+    pub algorithm: Algorithm,
+    // initialisation of every network, twin critics use seed + 1, and the
+    // randomness of every trainer but the DQN family's, which use train.seed
+    pub seed: u64,
+    // run outputs go here, along with the resolved config
+    pub output_dir: PathBuf,
+    // human play is appended here and read back by BehaviorCloning and Dqfd
+    pub demonstration_path: PathBuf,
+    pub environment: EnvironmentConfig,
+    pub network: NetworkConfig,
+    pub train: TrainConfig,
+    // head shapes of C51 and QrDqn
+    pub distributional: DistributionalConfig,
+    pub reinforce: ReinforceConfig,
+    pub a2c: A2cConfig,
+    // both the discrete and the continuous variant
+    pub ppo: PpoConfig,
+    // both Ddpg and Td3
+    pub ddpg: DdpgConfig,
+    pub sac: SacConfig,
+    // QLearning, Sarsa and ExpectedSarsa
+    pub tabular: TabularConfig,
+    pub cross_entropy: CrossEntropyConfig,
+    pub evolution_strategies: EvolutionStrategiesConfig,
+    pub neat: NeatConfig,
+    pub behavior_cloning: BehaviorCloningConfig,
+    // both Cql and Bcq
+    pub offline: OfflineConfig,
+}
+
+impl Default for ExperimentConfig {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            algorithm: Algorithm::Dqn,
+            seed: SEED,
+            output_dir: PathBuf::from("runs/default"),
+            demonstration_path: PathBuf::from("demonstrations.csv"),
+            environment: EnvironmentConfig::default(),
+            network: NetworkConfig::default(),
+            train: TrainConfig::default(),
+            distributional: DistributionalConfig::default(),
+            reinforce: ReinforceConfig::default(),
+            a2c: A2cConfig::default(),
+            ppo: PpoConfig::default(),
+            ddpg: DdpgConfig::default(),
+            sac: SacConfig::default(),
+            tabular: TabularConfig::default(),
+            cross_entropy: CrossEntropyConfig::default(),
+            evolution_strategies: EvolutionStrategiesConfig::default(),
+            neat: NeatConfig::default(),
+            behavior_cloning: BehaviorCloningConfig::default(),
+            offline: OfflineConfig::default(),
+        }
+    }
+}
+
+fn invalid(error: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+impl ExperimentConfig {
+    // The format follows the extension: .json is JSON, anything else TOML.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path: &Path = path.as_ref();
+        let contents: String = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "json") {
+            serde_json::from_str(&contents).map_err(invalid)
+        } else {
+            toml::from_str(&contents).map_err(invalid)
+        }
+    }
+
+    // Overrides one value from a dotted key=value assignment such as
+    // train.learning_rate=0.01. The value is read as TOML, falling back to a
+    // plain string, so paths and algorithm names need no quotes.
+    pub fn set(&mut self, assignment: &str) -> io::Result<()> {
+        let (key, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("Expected key=value, got {}", assignment)))?;
+        let value: toml::Value = match toml::from_str::<toml::Table>(&format!("value = {}", value.trim())) {
+            Ok(mut table) => table.remove("value").unwrap(),
+            Err(_) => toml::Value::String(value.trim().to_string()),
+        };
+        let mut root: toml::Value = toml::Value::try_from(&*self).map_err(invalid)?;
+        let mut table: &mut toml::Value = &mut root;
+        let mut fields: Vec<&str> = key.trim().split('.').collect();
+        let last: &str = fields.pop().unwrap();
+        for field in fields {
+            table = table
+                .get_mut(field)
+                .ok_or_else(|| invalid(format!("Unknown config section {}", field)))?;
+        }
+        table
+            .as_table_mut()
+            .ok_or_else(|| invalid(format!("{} is not a config section", key)))?
+            .insert(last.to_string(), value);
+        *self = root.try_into().map_err(invalid)?;
+        Ok(())
+    }
+
+    // Validates, makes output_dir absolute and places relative run outputs
+    // inside it, so the result describes exactly where this run writes and
+    // resolving it again changes nothing.
+    pub fn resolved(mut self) -> io::Result<Self> {
+        self.train.validate().map_err(invalid)?;
+        self.validate_tables().map_err(invalid)?;
+        self.check_tables().map_err(invalid)?;
+        self.network
+            .validate(self.environment.pixels.as_ref())
+            .map_err(invalid)?;
+        if self.environment.max_steps == 0 {
+            return Err(invalid("max_steps must be positive"));
+        }
+        if let Some(pixels) = &self.environment.pixels {
+            pixels.validate().map_err(invalid)?;
+            if self.algorithm.needs_state_vector() {
+                return Err(invalid(format!(
+                    "{} needs the state vector, not pixel observations",
+                    self.algorithm.name()
+                )));
+            }
+        }
+        self.output_dir = std::path::absolute(&self.output_dir)?;
+        if self.train.dataset_path.is_relative() {
+            self.train.dataset_path = self.output_dir.join(&self.train.dataset_path);
+        }
+        if let Some(directory) = &self.environment.trajectory_dir
+            && directory.is_relative()
+        {
+            self.environment.trajectory_dir = Some(self.output_dir.join(directory));
+        }
+        if self.train.recording.directory.is_relative() {
+            self.train.recording.directory = self.output_dir.join(&self.train.recording.directory);
+        }
+        Ok(self)
+    }
+
+    // every algorithm's table, whether or not this run reads it
+    fn validate_tables(&self) -> Result<(), String> {
+        let checks: [(&str, Result<(), String>); 12] = [
+            ("distributional", self.distributional.validate()),
+            ("reinforce", self.reinforce.validate()),
+            ("a2c", self.a2c.validate()),
+            ("ppo", self.ppo.validate()),
+            ("ddpg", self.ddpg.validate()),
+            ("sac", self.sac.validate()),
+            ("tabular", self.tabular.validate()),
+            ("cross_entropy", self.cross_entropy.validate()),
+            ("evolution_strategies", self.evolution_strategies.validate()),
+            ("neat", self.neat.validate()),
+            ("behavior_cloning", self.behavior_cloning.validate()),
+            ("offline", self.offline.validate()),
+        ];
+        for (table, check) in checks {
+            check.map_err(|error| format!("{} table: {}", table, error))?;
+        }
+        Ok(())
+    }
+
+    // An algorithm would silently ignore a changed table it does not read.
+    // The train table's seed is left out as --seed sets every seed, as are
+    // its paths: Cql and Bcq read the dataset, and record the recording
+    // settings, whatever the algorithm.
+    fn check_tables(&self) -> Result<(), String> {
+        let reads_train: bool = self.algorithm.tables().contains(&"train");
+        if !reads_train && self.train.recording.every > 0 {
+            return Err(format!(
+                "{} does not record while training, only the DQN family (dqn, dqfd, c51, qr_dqn) does",
+                self.algorithm.name()
+            ));
+        }
+        let train_defaults: TrainConfig = TrainConfig::default();
+        let train = TrainConfig {
+            seed: train_defaults.seed,
+            dataset_path: train_defaults.dataset_path.clone(),
+            recording: RecordingConfig {
+                every: self.train.recording.every,
+                ..train_defaults.recording.clone()
+            },
+            ..self.train.clone()
+        };
+        let compared: toml::Value = toml::Value::try_from(Self {
+            train,
+            ..self.clone()
+        })
+        .map_err(|error| error.to_string())?;
+        let defaults: toml::Value = toml::Value::try_from(Self::default()).map_err(|error| error.to_string())?;
+        for table in [
+            "train",
+            "distributional",
+            "reinforce",
+            "a2c",
+            "ppo",
+            "ddpg",
+            "sac",
+            "tabular",
+            "cross_entropy",
+            "evolution_strategies",
+            "neat",
+            "behavior_cloning",
+            "offline",
+        ] {
+            if !self.algorithm.tables().contains(&table) && compared.get(table) != defaults.get(table) {
+                return Err(format!(
+                    "{} does not read the {} table, only {}",
+                    self.algorithm.name(),
+                    table,
+                    self.algorithm.tables().join(" and ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Creates the output directory and writes this config into it as TOML.
+    pub fn write_resolved(&self) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.output_dir)?;
+        let path: PathBuf = self.output_dir.join(RESOLVED_CONFIG);
+        fs::write(&path, toml::to_string_pretty(self).map_err(invalid)?)?;
+        Ok(path)
+    }
+
+    // Named after the algorithm, next to the resolved config, so a run of
+    // another algorithm in the same directory never passes for this one's.
+    pub fn checkpoint_path(&self) -> PathBuf {
+        self.output_dir
+            .join(format!("{}.{}", self.algorithm.name(), CHECKPOINT_EXTENSION))
+    }
+
+    pub fn game(&self) -> game::Game {
+        self.environment.build(0)
+    }
+
+    // Networks that take a pixel observation start with the network table's
+    // convolutions. The rest, such as critics that also take the action,
+    // are linear throughout.
+    pub fn build_network(&self, inputs: usize, outputs: usize) -> model::Model {
+        match &self.environment.pixels {
+            Some(pixels) if !self.network.conv.is_empty() && inputs == pixels.observation_size() => {
+                self.network.build_image(pixels.shape(), outputs, self.seed)
+            }
+            _ => self.network.build(inputs, outputs, self.seed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn train_table_is_rejected_for_algorithms_that_ignore_it() {
+        let mut experiment = ExperimentConfig {
+            algorithm: Algorithm::A2c,
+            ..Default::default()
+        };
+        experiment.set("train.learning_rate=0.01").unwrap();
+        assert!(experiment.clone().resolved().is_err());
+        experiment.algorithm = Algorithm::C51;
+        assert!(experiment.resolved().is_ok());
+    }
+
+    #[test]
+    fn algorithm_tables_are_read_only_by_their_algorithm() {
+        let mut experiment = ExperimentConfig {
+            algorithm: Algorithm::Sac,
+            ..Default::default()
+        };
+        experiment.set("ppo.clip_range=0.1").unwrap();
+        assert!(experiment.clone().resolved().is_err());
+        experiment.algorithm = Algorithm::PpoContinuous;
+        let resolved: ExperimentConfig = experiment.resolved().unwrap();
+        assert_eq!(resolved.ppo.clip_range, 0.1);
+    }
+
+    #[test]
+    fn invalid_algorithm_tables_are_rejected() {
+        let mut experiment = ExperimentConfig {
+            algorithm: Algorithm::Neat,
+            ..Default::default()
+        };
+        experiment.set("tabular.gamma=1.5").unwrap();
+        let error: io::Error = experiment.resolved().unwrap_err();
+        assert!(error.to_string().starts_with("tabular table"));
+    }
+
+    #[test]
+    fn recording_while_training_is_rejected_outside_the_dqn_family() {
+        let mut experiment = ExperimentConfig {
+            algorithm: Algorithm::Sac,
+            ..Default::default()
+        };
+        experiment.train.recording.every = 5;
+        assert!(experiment.clone().resolved().is_err());
+        experiment.algorithm = Algorithm::QrDqn;
+        assert!(experiment.resolved().is_ok());
+    }
+
+    #[test]
+    fn pixels_are_rejected_for_algorithms_that_need_the_state_vector() {
+        let mut experiment = ExperimentConfig {
+            algorithm: Algorithm::Sarsa,
+            ..Default::default()
+        };
+        experiment.environment.pixels = Some(PixelConfig::default());
+        assert!(experiment.clone().resolved().is_err());
+        experiment.algorithm = Algorithm::Dqn;
+        assert!(experiment.resolved().is_ok());
+    }
+
+    #[test]
+    fn convolutions_apply_to_pixel_observations_only() {
+        let mut experiment = ExperimentConfig::default();
+        experiment.network.conv = vec![ConvConfig {
+            pool: Some(PoolConfig::default()),
+            ..Default::default()
+        }];
+        assert!(experiment.clone().resolved().is_err());
+        experiment.environment.pixels = Some(PixelConfig {
+            width: 8,
+            height: 8,
+            ..Default::default()
+        });
+        let experiment: ExperimentConfig = experiment.resolved().unwrap();
+        let game: game::Game = experiment.game();
+        let mut observation: ndarray::Array1<f32> = ndarray::Array1::zeros(game.observation_space);
+        game.observe(&mut observation);
+        let mut network: model::Model = experiment.build_network(game.observation_space, game.action_space);
+        assert_eq!(network.forward(&observation).len(), game.action_space);
+        // a critic that also takes the action stays linear
+        assert_eq!(
+            experiment.build_network(game.observation_space + 1, 1).num_layers,
+            experiment.network.hidden_layers.len() + 1
+        );
+    }
+
+    #[test]
+    fn seed_and_paths_are_accepted_for_every_algorithm() {
+        let mut experiment = ExperimentConfig {
+            algorithm: Algorithm::Cql,
+            ..Default::default()
+        };
+        experiment.train.seed = 7;
+        experiment.set("train.dataset_path=elsewhere.bin").unwrap();
+        experiment.set("train.recording.format=png").unwrap();
+        let resolved: ExperimentConfig = experiment.resolved().unwrap();
+        assert_eq!(resolved.clone().resolved().unwrap(), resolved);
+    }
+}
@@ -1,21 +1,29 @@
 use std::f32::consts::PI;
+use std::io;
+use std::sync::Arc;
 
 use crate::graphics;
 use crate::graphics::ENV_BOX_HEIGHT;
 use crate::graphics::ENV_BOX_WIDTH;
+use crate::pixels::{FrameStack, PixelConfig};
+use crate::trajectory::{Action, Step, TrajectoryLog};
+#[cfg(feature = "graphics")]
+use crate::trajectory::{self, Trajectory};
 use lazy_static::lazy_static;
-use macroquad::color::{BLACK, BLUE, Color, GRAY, PURPLE, WHITE};
-use macroquad::shapes::draw_rectangle;
+#[cfg(feature = "graphics")]
+use crate::renderer::{MacroquadRenderer, Renderer};
+#[cfg(feature = "graphics")]
+use macroquad::color::{BLACK, WHITE};
+#[cfg(feature = "graphics")]
 use macroquad::{
     input::{KeyCode, is_key_pressed},
     text::draw_text,
     window::{clear_background, next_frame},
 };
 use ndarray::Array1;
-use rand;
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "graphics")]
 use std::{thread, time::Duration};
-use strum::IntoEnumIterator;
-use strum_macros::EnumIter;
 use uom::ConstZero;
 use uom::si::acceleration::meter_per_second_squared;
 use uom::si::angle::radian;
@@ -24,70 +32,36 @@ use uom::si::f32::*;
 use uom::si::force::newton;
 use uom::si::length::meter;
 use uom::si::mass::kilogram;
-use uom::si::time::{millisecond, second};
+#[cfg(feature = "graphics")]
+use uom::si::time::millisecond;
+use uom::si::time::second;
 use uom::si::velocity::meter_per_second;
 
 lazy_static! {
-    static ref PARTICLE_SPEED: Velocity =
-        Velocity::new::<meter_per_second>(ENV_BOX_HEIGHT.value / 5.);
-    static ref PARTICLE_RADIUS: Length = *ENV_BOX_HEIGHT / 100.;
-    static ref PARTICLE_LIFTIME: Time = Time::new::<second>(1.);
     pub static ref MAX_STEPS: u16 = 3;
-    static ref MIN_HEIGHT: Length = *ENV_BOX_HEIGHT / 5.;
+    // top of the ground
+    pub static ref MIN_HEIGHT: Length = *ENV_BOX_HEIGHT / 5.;
     static ref MAX_ANGULAR_VEL: AngularVelocity =
         AngularVelocity::new::<radian_per_second>(PI / 4.);
     static ref MAX_VEL: Velocity = Velocity::new::<meter_per_second>(5.);
-    static ref DT: Time = Time::new::<second>(1.0);
+    pub static ref DT: Time = Time::new::<second>(1.0);
     static ref GRAVITY: Acceleration = Acceleration::new::<meter_per_second_squared>(9.81);
 }
 
-#[derive(Debug, EnumIter)]
-enum Engine {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
     LEFT,
     RIGHT,
     DOWN,
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct Pos {
     pub x: Length,
     pub y: Length,
 }
 
 #[derive(Clone)]
-struct JetParticle {
-    x: Length,
-    y: Length,
-    vx: Velocity,
-    vy: Velocity,
-    life: Time,
-}
-
-impl JetParticle {
-    fn new() -> Self {
-        Self {
-            x: Length::new::<meter>(0.),
-            y: Length::new::<meter>(0.),
-            vx: Velocity::new::<meter_per_second>(0.),
-            vy: Velocity::new::<meter_per_second>(0.),
-            life: Time::new::<second>(0.),
-        }
-    }
-
-    fn activate(&mut self, x: Length, y: Length, direction: Angle) {
-        self.x = x;
-        self.y = y;
-        self.life = Time::new::<second>(0.);
-        self.vx = *PARTICLE_SPEED * direction.cos();
-        self.vy = *PARTICLE_SPEED * direction.sin();
-    }
-
-    fn update(&mut self) {
-        self.x += self.vx * (*DT);
-        self.y += self.vy * (*DT);
-        self.life += *DT;
-    }
-}
-
 pub struct Rocket {
     pos: Pos,
     vx: Velocity,
@@ -99,8 +73,8 @@ pub struct Rocket {
     lander_angle: Angle,
     lander_length: Length,
     engine_dim: Length,
-    jet_particles: [JetParticle; 15],
-    particle_index: usize,
+    // engine fired by the last step, if any
+    firing: Option<Engine>,
     translational_engine_accel: Acceleration,
     angular_engine_accel: AngularAcceleration,
 }
@@ -112,6 +86,11 @@ const START_YVEL: f32 = 0.;
 
 impl Rocket {
     pub fn new() -> Self {
+        Self::new_using(&mut rand::rng())
+    }
+
+    // starting position randomness (RAND_X / RAND_Y) is drawn from rng
+    pub fn new_using(rng: &mut impl Rng) -> Self {
         let mass = Mass::new::<kilogram>(50.);
         let width = *ENV_BOX_WIDTH / 10.0;
         let height = *ENV_BOX_HEIGHT / 20.0;
@@ -123,12 +102,12 @@ impl Rocket {
         Self {
             pos: Pos {
                 x: if RAND_X {
-                    Length::new::<meter>(rand::random_range(0.0..ENV_BOX_WIDTH.value))
+                    Length::new::<meter>(rng.random_range(0.0..ENV_BOX_WIDTH.value))
                 } else {
                     *ENV_BOX_WIDTH
                 },
                 y: if RAND_Y {
-                    Length::new::<meter>(rand::random_range(0.0..ENV_BOX_HEIGHT.value))
+                    Length::new::<meter>(rng.random_range(0.0..ENV_BOX_HEIGHT.value))
                 } else {
                     *ENV_BOX_HEIGHT / 2.
                 },
@@ -142,8 +121,7 @@ impl Rocket {
             lander_angle: Angle::new::<radian>(-PI / 3.),
             lander_length: height,
             engine_dim: width / 4.,
-            jet_particles: core::array::from_fn(|_| JetParticle::new()),
-            particle_index: 0,
+            firing: None,
             translational_engine_accel: engine_accel,
             angular_engine_accel: side_accel,
         }
@@ -207,6 +185,7 @@ impl Rocket {
     }
 
     fn fire_engine(&mut self, engine: Engine) {
+        self.firing = Some(engine);
         match engine {
             Engine::RIGHT => {
                 self.vx -= Velocity::new::<meter_per_second>(1.); 
@@ -220,70 +199,215 @@ impl Rocket {
         }
     }
 
+    // continuous counterpart of fire_engine: positive thrust fires the left
+    // engine, negative the right one, scaled by the magnitude
+    fn throttle(&mut self, thrust: f32) {
+        self.firing = if thrust > 0. {
+            Some(Engine::LEFT)
+        } else if thrust < 0. {
+            Some(Engine::RIGHT)
+        } else {
+            None
+        };
+        self.vx += Velocity::new::<meter_per_second>(thrust.clamp(-1., 1.));
+    }
+
     fn update(&mut self) {
         self.pos.x += self.vx * (*DT);
     }
 
-    fn draw_engine(&self, engine: Engine) {
-        let engine_center_offset = self.engine_pos(engine);
-        graphics::adjusted_draw_rectangle_ex(
-            engine_center_offset.x + self.pos.x,
-            engine_center_offset.y + self.pos.y,
-            self.engine_dim,
-            self.engine_dim,
-            self.tilt,
-            GRAY,
-        );
-    }
-
-    fn draw(&self) -> () {
-        graphics::adjusted_draw_rectangle_ex(
-            self.pos.x,
-            self.pos.y,
-            self.width,
-            self.height,
-            self.tilt,
-            PURPLE,
-        );
-        for engine_type in Engine::iter() {
-            self.draw_engine(engine_type);
+    fn engine_quad(&self, engine: Engine) -> Quad {
+        let offset: Pos = self.engine_pos(engine);
+        Quad {
+            center: Pos {
+                x: offset.x + self.pos.x,
+                y: offset.y + self.pos.y,
+            },
+            width: self.engine_dim,
+            height: self.engine_dim,
+            tilt: self.tilt,
         }
-        let left_start: Pos = self.leg_pos(true, true);
-        let left_end: Pos = self.leg_pos(true, false);
-        let right_start: Pos = self.leg_pos(false, true);
-        let right_end: Pos = self.leg_pos(false, false);
-        graphics::adjusted_draw_line(left_start.x, left_start.y, left_end.x, left_end.y, BLUE);
-        graphics::adjusted_draw_line(right_start.x, right_start.y, right_end.x, right_end.y, BLUE);
-        for particle in &self.jet_particles {
-            graphics::adjusted_draw_circle(
-                particle.x,
-                particle.y,
-                *PARTICLE_RADIUS,
-                Color::new(
-                    1.,
-                    1.,
-                    1.,
-                    ((*PARTICLE_LIFTIME - particle.life) / *PARTICLE_LIFTIME).value,
-                ),
-            );
+    }
+
+    // where the fired engine's jet leaves and which way it points, away
+    // from the side the engine pushes
+    fn exhaust(&self) -> Option<Exhaust> {
+        let engine: Engine = self.firing?;
+        let direction: Angle = match engine {
+            Engine::RIGHT => self.tilt,
+            Engine::LEFT => self.tilt + Angle::new::<radian>(PI),
+            Engine::DOWN => self.tilt - Angle::new::<radian>(PI / 2.),
+        };
+        Some(Exhaust {
+            pos: self.engine_quad(engine).center,
+            direction,
+        })
+    }
+
+    fn snapshot(&self, steps: u16) -> Snapshot {
+        Snapshot {
+            body: Quad {
+                center: self.pos,
+                width: self.width,
+                height: self.height,
+                tilt: self.tilt,
+            },
+            engines: [Engine::LEFT, Engine::RIGHT, Engine::DOWN].map(|engine| self.engine_quad(engine)),
+            legs: [true, false].map(|left| (self.leg_pos(left, true), self.leg_pos(left, false))),
+            ground: *MIN_HEIGHT,
+            exhaust: self.exhaust(),
+            steps,
         }
     }
 }
 
+// A rectangle rotated counterclockwise by tilt around its center.
+#[derive(Clone, Copy, Debug)]
+pub struct Quad {
+    pub center: Pos,
+    pub width: Length,
+    pub height: Length,
+    pub tilt: Angle,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Exhaust {
+    pub pos: Pos,
+    pub direction: Angle,
+}
+
+// Everything a renderer needs to draw one frame, in world units with y
+// pointing up. Produced by the simulation, which never draws itself.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub body: Quad,
+    // left, right and down
+    pub engines: [Quad; 3],
+    // start and end of the left and right legs
+    pub legs: [(Pos, Pos); 2],
+    // height of the ground's top edge
+    pub ground: Length,
+    // jet of the engine fired by the last step
+    pub exhaust: Option<Exhaust>,
+    // steps taken in the episode, renderers advance their effects once per step
+    pub steps: u16,
+}
+
+#[derive(Clone)]
 pub struct Game {
     pub state: Rocket,
     pub action_space: usize,
+    pub continuous_action_space: usize,
     pub observation_space: usize,
     pub steps: u16,
+    // episode length, MAX_STEPS unless configured otherwise
+    pub max_steps: u16,
+    // when set, every reset draws the episode seed from this generator
+    rng: Option<rand::rngs::StdRng>,
+    // the current episode's start state was drawn from this, see restart
+    episode_seed: u64,
+    // image observations instead of Rocket::to_vec's state vector
+    pixels: Option<FrameStack>,
+    trajectories: Option<TrajectoryLog>,
+    // the write that stopped trajectory logging, see trajectory_error
+    trajectory_error: Option<Arc<io::Error>>,
 }
 
 impl Game {
     pub fn new() -> Self {
-        Self {
+        let mut game = Self {
             state: Rocket::new(),
             action_space: 2,
+            continuous_action_space: 1,
             observation_space: 2,
             steps: 0,
+            max_steps: *MAX_STEPS,
+            rng: None,
+            episode_seed: 0,
+            pixels: None,
+            trajectories: None,
+            trajectory_error: None,
+        };
+        game.reset();
+        game
+    }
+
+    // reproducible episodes: the same seed always yields the same start states
+    pub fn with_seed(seed: u64) -> Self {
+        let mut game = Self::new();
+        game.seed(seed);
+        game
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Some(rand::rngs::StdRng::seed_from_u64(seed));
+        self.reset();
+    }
+
+    // Switches observe to stacked grayscale frames, see pixels::FrameStack.
+    pub fn use_pixels(&mut self, config: PixelConfig) {
+        self.observation_space = config.observation_size();
+        let mut pixels = FrameStack::new(config);
+        pixels.restart(&self.snapshot());
+        self.pixels = Some(pixels);
+    }
+
+    // Writes what agents observe into output, observation_space values long.
+    pub fn observe(&self, output: &mut Array1<f32>) {
+        match &self.pixels {
+            Some(pixels) => pixels.to_vec(output),
+            None => self.state.to_vec(output),
+        }
+    }
+
+    // Logs every finished episode from now on, or stops logging with None.
+    pub fn log_trajectories(&mut self, log: Option<TrajectoryLog>) {
+        self.trajectories = log;
+        self.trajectory_error = None;
+        if self.steps == 0
+            && let Some(log) = &mut self.trajectories
+        {
+            log.begin(self.episode_seed);
+        }
+    }
+
+    // Stepping never fails, so when an episode cannot be written logging
+    // stops and the error is kept here for the game's owner to check.
+    pub fn trajectory_error(&self) -> Option<&io::Error> {
+        self.trajectory_error.as_deref()
+    }
+
+    pub fn episode_seed(&self) -> u64 {
+        self.episode_seed
+    }
+
+    // what the agent sees before a step, kept only while logging
+    fn logged_observation(&self) -> Option<Array1<f32>> {
+        self.trajectories.as_ref().map(|_| {
+            let mut observation: Array1<f32> = Array1::zeros(self.observation_space);
+            self.observe(&mut observation);
+            observation
+        })
+    }
+
+    fn log_step(&mut self, observation: Array1<f32>, action: Action, reward: f32, finished: bool) {
+        if let Some(log) = &mut self.trajectories {
+            log.push(Step {
+                action,
+                reward,
+                observation,
+            });
+            if finished && let Err(error) = log.finish() {
+                self.trajectories = None;
+                self.trajectory_error = Some(Arc::new(error));
+            }
+        }
+    }
+
+    fn record_frame(&mut self) {
+        let snapshot: Snapshot = self.snapshot();
+        if let Some(pixels) = &mut self.pixels {
+            pixels.push(&snapshot);
         }
     }
 }
@@ -295,7 +419,9 @@ impl Game {
 impl Game {
     #[allow(non_snake_case)]
     pub fn step(&mut self, choice: usize) -> (f32, bool) {
+        let observation: Option<Array1<f32>> = self.logged_observation();
         self.steps += 1;
+        self.state.firing = None;
         let mut reward: f32 = 0.;
         match choice {
             0 => Ok(self.state.fire_engine(Engine::RIGHT)),
@@ -306,27 +432,66 @@ impl Game {
         let mut finished = false;
         self.state.update();
         reward -= (self.state.pos.x - *ENV_BOX_WIDTH / 2.).abs().value / 1.;
-        if self.steps >= *MAX_STEPS {
+        if self.steps >= self.max_steps {
             finished = true;
         }
+        self.record_frame();
+        if let Some(observation) = observation {
+            self.log_step(observation, Action::Discrete(choice), reward, finished);
+        }
         return (reward, finished);
     }
 
+    // action[0] in [-1, 1]: side engine throttle, see Rocket::throttle
+    pub fn step_continuous(&mut self, action: &Array1<f32>) -> (f32, bool) {
+        let observation: Option<Array1<f32>> = self.logged_observation();
+        self.steps += 1;
+        self.state.throttle(action[0]);
+        self.state.update();
+        let reward: f32 = -(self.state.pos.x - *ENV_BOX_WIDTH / 2.).abs().value;
+        let finished: bool = self.steps >= self.max_steps;
+        self.record_frame();
+        if let Some(observation) = observation {
+            self.log_step(observation, Action::Continuous(action.clone()), reward, finished);
+        }
+        (reward, finished)
+    }
+
+    // step or step_continuous, whichever the action is for
+    pub fn apply(&mut self, action: &Action) -> (f32, bool) {
+        match action {
+            Action::Discrete(choice) => self.step(*choice),
+            Action::Continuous(action) => self.step_continuous(action),
+        }
+    }
+
+    // Starts a new episode from a seed drawn from the game's generator, or
+    // from the thread's when the game is unseeded.
     pub fn reset(&mut self) {
+        let seed: u64 = match &mut self.rng {
+            Some(rng) => rng.random(),
+            None => rand::rng().random(),
+        };
+        self.restart(seed);
+    }
+
+    // Starts the episode with the given seed again: the same seed always
+    // yields the same start state, so logged episodes can be replayed.
+    pub fn restart(&mut self, episode_seed: u64) {
         self.steps = 0;
-        self.state = Rocket::new();
+        self.episode_seed = episode_seed;
+        self.state = Rocket::new_using(&mut rand::rngs::StdRng::seed_from_u64(episode_seed));
+        let snapshot: Snapshot = self.snapshot();
+        if let Some(pixels) = &mut self.pixels {
+            pixels.restart(&snapshot);
+        }
+        if let Some(log) = &mut self.trajectories {
+            log.begin(episode_seed);
+        }
     }
 
-    pub fn draw(&self) {
-        clear_background(BLACK);
-        draw_rectangle(
-            0.,
-            *graphics::GRAPHICS_SCALAR * (ENV_BOX_HEIGHT.value * 4. / 5.),
-            *graphics::GRAPHICS_SCALAR * ENV_BOX_WIDTH.value,
-            *graphics::GRAPHICS_SCALAR * ENV_BOX_HEIGHT.value / 5.,
-            WHITE,
-        );
-        self.state.draw();
+    pub fn snapshot(&self) -> Snapshot {
+        self.state.snapshot(self.steps)
     }
 
     #[inline]
@@ -335,28 +500,76 @@ impl Game {
     }
 }
 
-pub async fn run_game(mut choose: impl FnMut() -> usize) {
-    let mut new_game = Game::new();
-    let mut score: f32 = 0.;
-    new_game.draw();
+// Plays one episode of game on screen. The chooser receives the game's
+// observations. Returns the reward of every step, in the order the choices
+// were made.
+#[cfg(feature = "graphics")]
+pub async fn run_game(game: Game, choose: impl FnMut(&Array1<f32>) -> usize) -> Vec<f32> {
+    play(game, None, choose, |game, choice| game.step(*choice)).await
+}
+
+#[cfg(feature = "graphics")]
+pub async fn run_game_continuous(game: Game, choose: impl FnMut(&Array1<f32>) -> Array1<f32>) -> Vec<f32> {
+    play(game, None, choose, |game, action| game.step_continuous(action)).await
+}
+
+// run_game's replay mode: checks that the logged episode re-simulates bit
+// for bit, see trajectory::replay, then plays it on screen. game must be
+// configured like the one that logged it.
+#[cfg(feature = "graphics")]
+pub async fn run_replay(game: Game, trajectory: &Trajectory) -> Result<Vec<f32>, String> {
+    let mut check: Game = game.clone();
+    check.log_trajectories(None);
+    trajectory::replay(&mut check, trajectory)?;
+    let mut actions = trajectory.steps.iter().map(|step| step.action.clone());
+    Ok(play(
+        game,
+        Some(trajectory.seed),
+        |_| actions.next().unwrap(),
+        |game, action| game.apply(action),
+    )
+    .await)
+}
+
+// Restarts the given episode seed, or resets to a new episode without one.
+#[cfg(feature = "graphics")]
+async fn play<A: std::fmt::Display>(
+    mut new_game: Game,
+    episode_seed: Option<u64>,
+    mut choose: impl FnMut(&Array1<f32>) -> A,
+    step: impl Fn(&mut Game, &A) -> (f32, bool),
+) -> Vec<f32> {
+    match episode_seed {
+        Some(seed) => new_game.restart(seed),
+        None => new_game.reset(),
+    }
+    let mut renderer = MacroquadRenderer::new();
+    let mut observation: Array1<f32> = Array1::zeros(new_game.observation_space);
+    let mut rewards: Vec<f32> = vec![];
+    renderer.draw(&new_game.snapshot());
     thread::sleep(Duration::from_millis(DT.get::<millisecond>() as u64));
     loop {
-        let choice: usize = choose();
+        new_game.observe(&mut observation);
+        let choice: A = choose(&observation);
         println!("Chose: {}", choice);
 
-        new_game.draw();
+        renderer.draw(&new_game.snapshot());
         thread::sleep(Duration::from_millis(DT.get::<millisecond>() as u64));
         next_frame().await;
 
-        let (reward, finished) = new_game.step(choice);
-        score += reward;
+        let (reward, finished) = step(&mut new_game, &choice);
+        rewards.push(reward);
 
         println!("Reward: {}", reward);
         if finished {
             break;
         }
     }
+    if let Some(error) = new_game.trajectory_error() {
+        eprintln!("Failed to write trajectory: {}", error);
+    }
 
+    let score: f32 = rewards.iter().sum();
     loop {
         clear_background(BLACK);
         draw_text(&format!("You scored {}", score), 100.0, 100.0, 50.0, WHITE);
@@ -367,4 +580,5 @@ pub async fn run_game(mut choose: impl FnMut() -> usize) {
 
         next_frame().await;
     }
+    rewards
 }
@@ -1,4 +1,5 @@
 use lazy_static::lazy_static;
+#[cfg(feature = "graphics")]
 use macroquad::{color::Color, math::Vec2, shapes::{DrawRectangleParams, draw_circle, draw_line, draw_rectangle_ex}};
 use uom::si::{f32::{Angle, Length}, length::meter};
 
@@ -19,6 +20,7 @@ pub fn transform_with_units(vector: &mut Pos, angle: Angle) {
     }
 }
 
+#[cfg(feature = "graphics")]
 #[inline]
 pub fn adjusted_draw_circle(x: Length, y: Length, radius: Length, color: Color) {
     draw_circle(
@@ -29,6 +31,7 @@ pub fn adjusted_draw_circle(x: Length, y: Length, radius: Length, color: Color)
     );
 }
 
+#[cfg(feature = "graphics")]
 #[inline]
 pub fn adjusted_draw_rectangle_ex(
     x: Length,
@@ -51,6 +54,7 @@ pub fn adjusted_draw_rectangle_ex(
     );
 }
 
+#[cfg(feature = "graphics")]
 #[inline]
 pub fn adjusted_draw_line(x1: Length, y1: Length, x2: Length, y2: Length, color: Color) {
     draw_line(
@@ -0,0 +1,236 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use ndarray::Array1;
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+
+// Hyperparameters of behavior_cloning, the behavior_cloning table of an
+// experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BehaviorCloningConfig {
+    pub epochs: u16,
+    pub minibatch_size: usize,
+    pub learning_rate: f32,
+}
+
+impl Default for BehaviorCloningConfig {
+    fn default() -> Self {
+        Self {
+            epochs: 200,
+            minibatch_size: 32,
+            learning_rate: 0.01,
+        }
+    }
+}
+
+impl BehaviorCloningConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.epochs == 0 || self.minibatch_size == 0 {
+            return Err("epochs and minibatch_size must be positive".to_string());
+        }
+        if !(self.learning_rate > 0. && self.learning_rate.is_finite()) {
+            return Err(format!("learning_rate must be positive, got {}", self.learning_rate));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Demonstration {
+    pub observation: Array1<f32>,
+    pub action: usize,
+    pub reward: f32,
+    // last step of its episode, the next line starts a new one
+    pub done: bool,
+}
+
+// One demonstration per line: action, reward, done (0 or 1) and then the
+// observation, comma separated. Appends so that several play sessions
+// accumulate in one file.
+pub fn append_demonstrations(path: impl AsRef<Path>, demonstrations: &[Demonstration]) -> io::Result<()> {
+    let mut writer = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+    for demonstration in demonstrations {
+        write!(
+            writer,
+            "{},{},{}",
+            demonstration.action, demonstration.reward, demonstration.done as u8
+        )?;
+        for value in demonstration.observation.iter() {
+            write!(writer, ",{}", value)?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+pub fn load_demonstrations(path: impl AsRef<Path>) -> io::Result<Vec<Demonstration>> {
+    let invalid = |line: usize| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Malformed demonstration on line {}", line + 1),
+        )
+    };
+    let mut demonstrations: Vec<Demonstration> = vec![];
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split(',');
+        let action: usize = fields.next().unwrap().trim().parse().map_err(|_| invalid(number))?;
+        let reward: f32 = fields
+            .next()
+            .and_then(|field| field.trim().parse().ok())
+            .ok_or_else(|| invalid(number))?;
+        let done: bool = match fields.next().map(str::trim) {
+            Some("0") => false,
+            Some("1") => true,
+            _ => return Err(invalid(number)),
+        };
+        let observation: Array1<f32> = fields
+            .map(|field| field.trim().parse::<f32>())
+            .collect::<Result<Array1<f32>, _>>()
+            .map_err(|_| invalid(number))?;
+        demonstrations.push(Demonstration {
+            observation,
+            action,
+            reward,
+            done,
+        });
+    }
+    Ok(demonstrations)
+}
+
+// fraction of demonstrations where the greedy policy picks the demonstrated action
+pub fn accuracy(policy: &mut model::Model, demonstrations: &[Demonstration]) -> f32 {
+    let correct: usize = demonstrations
+        .iter()
+        .filter(|demonstration| model::argmax(policy.forward(&demonstration.observation)) == demonstration.action)
+        .count();
+    correct as f32 / demonstrations.len() as f32
+}
+
+// Supervised fit of the policy's softmax to the demonstrated actions with
+// cross-entropy loss. Returns the mean loss of every epoch.
+pub fn behavior_cloning(
+    policy: &mut model::Model,
+    demonstrations: &[Demonstration],
+    config: &BehaviorCloningConfig,
+    seed: u64,
+) -> Vec<f32> {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut indices: Vec<usize> = (0..demonstrations.len()).collect();
+    let mut history: Vec<f32> = vec![];
+    for epoch in 0..config.epochs {
+        indices.shuffle(&mut rng);
+        let mut loss: f32 = 0.;
+        for minibatch in indices.chunks(config.minibatch_size) {
+            for &i in minibatch {
+                let demonstration: &Demonstration = &demonstrations[i];
+                let probabilities: Array1<f32> = model::softmax(policy.forward(&demonstration.observation));
+                loss -= probabilities[demonstration.action].max(f32::EPSILON).ln();
+                // d(cross-entropy)/dlogits = p - onehot
+                let mut derivative: Array1<f32> = probabilities;
+                derivative[demonstration.action] -= 1.;
+                policy.backprop(&demonstration.observation, &derivative);
+            }
+            policy.apply_gradients(config.learning_rate / minibatch.len() as f32);
+        }
+        loss /= demonstrations.len() as f32;
+        if epoch.is_multiple_of((config.epochs / 10).max(1)) {
+            println!(
+                "Epoch {}\tLoss: {}\tAccuracy: {}",
+                epoch,
+                loss,
+                accuracy(policy, demonstrations)
+            );
+        }
+        history.push(loss);
+    }
+    history
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::train::SEED;
+
+    fn temporary(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("demonstrations_{}_{}.csv", name, std::process::id()))
+    }
+
+    fn demonstration(action: usize, observation: Vec<f32>, done: bool) -> Demonstration {
+        Demonstration {
+            observation: Array1::from(observation),
+            action,
+            reward: action as f32 - 0.5,
+            done,
+        }
+    }
+
+    #[test]
+    fn appended_sessions_load_back_in_order() {
+        let path: PathBuf = temporary("append");
+        let first: Vec<Demonstration> = vec![demonstration(1, vec![0.25, -1.5], false), demonstration(0, vec![2., 0.], true)];
+        let second: Vec<Demonstration> = vec![demonstration(2, vec![-0.125, 3.], true)];
+        append_demonstrations(&path, &first).unwrap();
+        append_demonstrations(&path, &second).unwrap();
+        let loaded: Vec<Demonstration> = load_demonstrations(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        for (loaded, written) in loaded.iter().zip(first.iter().chain(&second)) {
+            assert_eq!(loaded.observation, written.observation);
+            assert_eq!(loaded.action, written.action);
+            assert_eq!(loaded.reward, written.reward);
+            assert_eq!(loaded.done, written.done);
+        }
+    }
+
+    #[test]
+    fn blank_lines_are_skipped_and_malformed_ones_rejected() {
+        let path: PathBuf = temporary("malformed");
+        fs::write(&path, "1, 0.5, 0, 0.25, 1\n\n0,-1,1,2,3\n").unwrap();
+        let loaded: Vec<Demonstration> = load_demonstrations(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].observation, Array1::from(vec![0.25, 1.]));
+        for contents in ["1,0.5,0,0.25\nx,0.5,0,0.25\n", "1,0.5,2,0.25\n", "1,0.5,0,0.25,nan?\n", "1\n"] {
+            fs::write(&path, contents).unwrap();
+            let error: io::Error = load_demonstrations(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?} was accepted", contents);
+        }
+        fs::write(&path, "1,0.5,0,0.25\nx,0.5,0,0.25\n").unwrap();
+        assert!(load_demonstrations(&path).unwrap_err().to_string().ends_with("line 2"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cloning_fits_the_demonstrated_actions() {
+        // the action is the sign of the first feature
+        let demonstrations: Vec<Demonstration> = (0..16)
+            .map(|i| {
+                let x: f32 = i as f32 / 4. - 2. + 0.125;
+                demonstration((x > 0.) as usize, vec![x, 1.], false)
+            })
+            .collect();
+        let mut policy = model::Model::with_seed(SEED);
+        policy.add_layer(2, 2, false);
+        let config = BehaviorCloningConfig {
+            epochs: 100,
+            minibatch_size: 4,
+            learning_rate: 0.5,
+        };
+        let history: Vec<f32> = behavior_cloning(&mut policy, &demonstrations, &config, SEED);
+        assert_eq!(history.len(), 100);
+        assert!(history[99] < history[0]);
+        assert_eq!(accuracy(&mut policy, &demonstrations), 1.);
+    }
+}
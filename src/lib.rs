@@ -1,6 +1,24 @@
+pub mod a2c;
+pub mod ddpg;
 pub mod debug;
+pub mod distributional;
+pub mod evolution;
+pub mod experiment;
 pub mod game;
 pub mod graphics;
+pub mod imitation;
 pub mod model;
+pub mod neat;
+pub mod offline;
+pub mod pixels;
+pub mod ppo;
+pub mod rasterizer;
+pub mod recording;
+pub mod reinforce;
+pub mod renderer;
+pub mod sac;
+pub mod tabular;
 pub mod train;
+pub mod trajectory;
+pub mod transitions;
 pub mod test;
@@ -1,36 +1,582 @@
-use lunar_lander_rl::{game, model, train};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use clap::{Parser, Subcommand};
+use lunar_lander_rl::experiment::{Algorithm, EnvironmentConfig, ExperimentConfig};
+use lunar_lander_rl::recording::{self, RecordingConfig};
+use lunar_lander_rl::trajectory::{self, Trajectory};
+use lunar_lander_rl::{a2c, ddpg, distributional, evolution, game, imitation, model, neat, offline, ppo, reinforce, sac, tabular, train};
+#[cfg(feature = "graphics")]
+use macroquad::input::{KeyCode, is_key_down};
+#[cfg(feature = "graphics")]
 use macroquad::window::Conf;
+use ndarray::Array1;
+use rand::{Rng, SeedableRng};
+
+#[derive(Parser)]
+#[command(about = "Reinforcement learning agents for the lunar lander")]
+struct Cli {
+    /// TOML or JSON experiment file, defaults when omitted
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Seed of the networks, the training run and the environment
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+    /// Where the run's checkpoint, replay buffer and resolved config live
+    #[arg(long, global = true)]
+    output_dir: Option<PathBuf>,
+    /// Override a config value, e.g. --set train.learning_rate=0.01
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    overrides: Vec<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Train the configured algorithm, then watch it unless headless
+    Train {
+        /// Never open a window, implied in builds without graphics
+        #[arg(long)]
+        headless: bool,
+        /// Continue DQN from the replay buffer at the config's dataset path
+        #[arg(long)]
+        resume: bool,
+    },
+    /// Score the saved checkpoint over seeded episodes
+    Eval {
+        #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+        episodes: u64,
+    },
+    /// Record greedy episodes of the saved checkpoint as GIFs or PNG frames
+    Record {
+        #[arg(long, default_value_t = 1)]
+        episodes: u64,
+    },
+    /// Re-simulate a logged episode and check it matches bit for bit
+    Replay {
+        trajectory: PathBuf,
+        /// Also play it in a window
+        #[cfg(feature = "graphics")]
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Watch the saved checkpoint fly
+    #[cfg(feature = "graphics")]
+    Play,
+    /// Fly with the keyboard and record the demonstrations
+    #[cfg(feature = "graphics")]
+    Human,
+    /// Time simulation steps and network updates
+    Bench {
+        #[arg(long, default_value_t = 10000)]
+        iterations: usize,
+    },
+}
+
+#[cfg(feature = "graphics")]
+type DiscreteChoice = Box<dyn FnMut(&Array1<f32>) -> usize>;
+#[cfg(feature = "graphics")]
+type ContinuousChoice = Box<dyn FnMut(&Array1<f32>) -> Array1<f32>>;
+
+// A trained agent, reduced to how it picks actions so it can be watched.
+#[cfg(feature = "graphics")]
+enum Policy {
+    Discrete(DiscreteChoice),
+    Continuous(ContinuousChoice),
+}
+
+// Builds without graphics have no window to watch an agent in.
+#[cfg(not(feature = "graphics"))]
+struct Policy;
+
+impl Policy {
+    #[cfg(feature = "graphics")]
+    fn discrete(choose: impl FnMut(&Array1<f32>) -> usize + 'static) -> Self {
+        Policy::Discrete(Box::new(choose))
+    }
+
+    #[cfg(not(feature = "graphics"))]
+    fn discrete(_: impl FnMut(&Array1<f32>) -> usize) -> Self {
+        Policy
+    }
+
+    #[cfg(feature = "graphics")]
+    fn continuous(choose: impl FnMut(&Array1<f32>) -> Array1<f32> + 'static) -> Self {
+        Policy::Continuous(Box::new(choose))
+    }
 
-const HUMAN_PLAYER: bool = false;
+    #[cfg(not(feature = "graphics"))]
+    fn continuous(_: impl FnMut(&Array1<f32>) -> Array1<f32>) -> Self {
+        Policy
+    }
+
+    #[cfg(feature = "graphics")]
+    async fn watch(self, game: game::Game) {
+        match self {
+            Policy::Discrete(choose) => game::run_game(game, choose).await,
+            Policy::Continuous(choose) => game::run_game_continuous(game, choose).await,
+        };
+    }
+}
+
+fn main() {
+    let cli: Cli = Cli::parse();
+    let experiment: ExperimentConfig = load_experiment(&cli);
+    match cli.command {
+        #[cfg(feature = "graphics")]
+        Command::Train { headless, resume } => {
+            let policy: Policy = train_experiment(&experiment, resume);
+            if !headless {
+                macroquad::Window::from_config(window_conf(), policy.watch(experiment.game()));
+            }
+        }
+        #[cfg(not(feature = "graphics"))]
+        Command::Train { resume, .. } => {
+            train_experiment(&experiment, resume);
+        }
+        Command::Eval { episodes } => evaluate(&experiment, episodes),
+        Command::Record { episodes } => record(&experiment, episodes),
+        #[cfg(feature = "graphics")]
+        Command::Replay { trajectory, watch } => {
+            let (game, trajectory) = replay(&experiment, &trajectory);
+            if watch {
+                macroquad::Window::from_config(window_conf(), async move {
+                    game::run_replay(game, &trajectory).await.expect("Replay diverged");
+                });
+            }
+        }
+        #[cfg(not(feature = "graphics"))]
+        Command::Replay { trajectory } => {
+            replay(&experiment, &trajectory);
+        }
+        #[cfg(feature = "graphics")]
+        Command::Play => {
+            let mut policy: model::Model = load_checkpoint(&experiment);
+            let game: game::Game = experiment.game();
+            macroquad::Window::from_config(window_conf(), async move {
+                game::run_game(game, |observation| model::argmax(policy.forward(observation))).await;
+            });
+        }
+        #[cfg(feature = "graphics")]
+        Command::Human => macroquad::Window::from_config(window_conf(), record_demonstrations(experiment)),
+        Command::Bench { iterations } => bench(&experiment, iterations),
+    }
+}
+
+// The config file or defaults, then the flags, then the --set overrides.
+fn load_experiment(cli: &Cli) -> ExperimentConfig {
+    let mut experiment: ExperimentConfig = match &cli.config {
+        Some(path) => ExperimentConfig::load(path).expect("Failed to load experiment config"),
+        None => ExperimentConfig::default(),
+    };
+    if let Some(seed) = cli.seed {
+        experiment.seed = seed;
+        experiment.train.seed = seed;
+        experiment.environment.seed = Some(seed);
+    }
+    if let Some(output_dir) = &cli.output_dir {
+        experiment.output_dir = output_dir.clone();
+    }
+    for assignment in &cli.overrides {
+        experiment.set(assignment).expect("Invalid config override");
+    }
+    experiment.resolved().expect("Invalid experiment config")
+}
+
+// Policies that are the argmax of a single network are checkpointed, so eval,
+// record and play can rebuild them from the config.
+fn save_checkpoint(experiment: &ExperimentConfig, policy: &model::Model) {
+    assert!(experiment.algorithm.is_checkpointed());
+    let path: PathBuf = experiment.checkpoint_path();
+    policy.save_parameters(&path).expect("Failed to save checkpoint");
+    println!("Saved checkpoint to {}", path.display());
+}
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let mut game: game::Game = game::Game::new();
-    if HUMAN_PLAYER {
-        game::run_game(choose).await;
-    } else {
-        let mut agent: model::Model = model::Model::new();
-        agent.add_layer(game.observation_space, 512, true);
-        agent.add_layer(512, 256, true);
-        agent.add_layer(256, 64, true);
-        agent.add_layer(64, game.action_space, false);
-        train::train(&mut game, &mut agent).await;
+// Exits for algorithms that are never checkpointed.
+fn load_checkpoint(experiment: &ExperimentConfig) -> model::Model {
+    if !experiment.algorithm.is_checkpointed() {
+        eprintln!(
+            "{} policies are not checkpointed, only those that are the argmax of one network can be loaded",
+            experiment.algorithm.name()
+        );
+        process::exit(1);
+    }
+    let game: game::Game = experiment.game();
+    let mut policy: model::Model = experiment.build_network(game.observation_space, game.action_space);
+    if let Err(error) = policy.load_parameters(experiment.checkpoint_path()) {
+        eprintln!(
+            "Failed to load the checkpoint {}: {}. Train {} into this output directory first",
+            experiment.checkpoint_path().display(),
+            error,
+            experiment.algorithm.name()
+        );
+        process::exit(1);
+    }
+    policy.set_training(false);
+    policy
+}
+
+fn greedy(mut policy: model::Model) -> Policy {
+    Policy::discrete(move |observation| model::argmax(policy.forward(observation)))
+}
+
+// Writes the resolved config into the output directory, then trains.
+fn train_experiment(experiment: &ExperimentConfig, resume: bool) -> Policy {
+    let resolved: PathBuf = experiment.write_resolved().expect("Failed to write resolved config");
+    println!("Experiment {}, resolved config at {}", experiment.name, resolved.display());
+    let mut game: game::Game = experiment.game();
+    let policy: Policy = train_algorithm(experiment, &mut game, resume);
+    check_trajectories(&game);
+    policy
+}
+
+// Games log trajectories without failing a step, so their owners check
+// afterwards whether every episode was written.
+fn check_trajectories(game: &game::Game) {
+    if let Some(error) = game.trajectory_error() {
+        eprintln!("Failed to write trajectory: {}", error);
+        process::exit(1);
+    }
+}
+
+fn train_algorithm(experiment: &ExperimentConfig, game: &mut game::Game, resume: bool) -> Policy {
+    let head: distributional::Head = match experiment.algorithm {
+        Algorithm::Dqn => {
+            let mut agent: model::Model = experiment.build_network(game.observation_space, game.action_space);
+            if resume {
+                train::resume(game, &mut agent, &experiment.train).expect("Failed to load replay buffer");
+            } else {
+                train::train(game, &mut agent, &experiment.train);
+            }
+            save_checkpoint(experiment, &agent);
+            return greedy(agent);
+        }
+        Algorithm::C51 => experiment.distributional.categorical(),
+        Algorithm::QrDqn => experiment.distributional.quantile(),
+        Algorithm::Reinforce => {
+            let mut policy: model::Model = experiment.build_network(game.observation_space, game.action_space);
+            let mut baseline: model::Model = experiment.build_network(game.observation_space, 1);
+            reinforce::train(game, &mut policy, Some(&mut baseline), &experiment.reinforce, experiment.seed);
+            save_checkpoint(experiment, &policy);
+            return greedy(policy);
+        }
+        Algorithm::A2c => {
+            let mut games: Vec<game::Game> = (0..experiment.a2c.envs).map(|i| experiment.environment.build(i as u64)).collect();
+            let mut agent = a2c::ActorCritic::Separate {
+                actor: experiment.build_network(game.observation_space, game.action_space),
+                critic: experiment.build_network(game.observation_space, 1),
+            };
+            a2c::train(&mut games, &mut agent, &experiment.a2c, experiment.seed);
+            games.iter().for_each(check_trajectories);
+            return Policy::discrete(move |observation| agent.greedy_action(observation));
+        }
+        Algorithm::Ppo => {
+            let mut policy = ppo::Policy::Categorical(experiment.build_network(game.observation_space, game.action_space));
+            let mut critic: model::Model = experiment.build_network(game.observation_space, 1);
+            ppo::train(game, &mut policy, &mut critic, &experiment.ppo, experiment.seed);
+            let ppo::Policy::Categorical(logits) = policy else {
+                unreachable!()
+            };
+            save_checkpoint(experiment, &logits);
+            return greedy(logits);
+        }
+        Algorithm::PpoContinuous => {
+            let mut policy = ppo::Policy::gaussian(
+                experiment.build_network(game.observation_space, game.continuous_action_space),
+                experiment.ppo.initial_log_std,
+            );
+            let mut critic: model::Model = experiment.build_network(game.observation_space, 1);
+            ppo::train(game, &mut policy, &mut critic, &experiment.ppo, experiment.seed);
+            return Policy::continuous(move |observation| match policy.deterministic_action(observation) {
+                ppo::Action::Continuous(action) => action,
+                ppo::Action::Discrete(_) => unreachable!(),
+            });
+        }
+        Algorithm::Ddpg | Algorithm::Td3 => {
+            let critic_inputs: usize = game.observation_space + game.continuous_action_space;
+            let mut agent = ddpg::DeterministicActorCritic::new(
+                experiment.build_network(game.observation_space, game.continuous_action_space),
+                vec![
+                    experiment.build_network(critic_inputs, 1),
+                    experiment.network.build(critic_inputs, 1, experiment.seed + 1),
+                ],
+                if matches!(experiment.algorithm, Algorithm::Td3) {
+                    ddpg::Variant::Td3
+                } else {
+                    ddpg::Variant::Ddpg
+                },
+            );
+            ddpg::train(game, &mut agent, &experiment.ddpg, experiment.seed);
+            return Policy::continuous(move |observation| agent.act(observation));
+        }
+        Algorithm::Sac => {
+            let action_dims: usize = game.continuous_action_space;
+            let critic_inputs: usize = game.observation_space + action_dims;
+            let actor = model::GaussianPolicy::new(
+                experiment.build_network(game.observation_space, 2 * action_dims),
+                action_dims,
+                model::LogStd::StateDependent,
+                true,
+            );
+            let mut agent = sac::SoftActorCritic::new(
+                actor,
+                vec![
+                    experiment.build_network(critic_inputs, 1),
+                    experiment.network.build(critic_inputs, 1, experiment.seed + 1),
+                ],
+                experiment.sac.initial_alpha,
+                -(action_dims as f32),
+            );
+            sac::train(game, &mut agent, &experiment.sac, experiment.seed);
+            return Policy::continuous(move |observation| agent.actor.deterministic_action(observation));
+        }
+        Algorithm::QLearning | Algorithm::Sarsa | Algorithm::ExpectedSarsa => {
+            let method: tabular::Method = match experiment.algorithm {
+                Algorithm::Sarsa => tabular::Method::Sarsa,
+                Algorithm::ExpectedSarsa => tabular::Method::ExpectedSarsa,
+                _ => tabular::Method::QLearning,
+            };
+            let mut agent = tabular::TabularAgent::new(
+                experiment.tabular.discretizer(),
+                game.action_space,
+                method,
+                &experiment.tabular,
+                experiment.seed,
+            );
+            tabular::train(game, &mut agent, &experiment.tabular);
+            return Policy::discrete(move |observation| agent.greedy_action(observation));
+        }
+        Algorithm::CrossEntropy | Algorithm::EvolutionStrategies => {
+            let mut policy: model::Model = experiment.build_network(game.observation_space, game.action_space);
+            if matches!(experiment.algorithm, Algorithm::CrossEntropy) {
+                evolution::cross_entropy_method(
+                    &mut policy,
+                    &experiment.environment,
+                    &experiment.cross_entropy,
+                    experiment.seed,
+                );
+            } else {
+                evolution::evolution_strategies(
+                    &mut policy,
+                    &experiment.environment,
+                    &experiment.evolution_strategies,
+                    experiment.seed,
+                );
+            }
+            save_checkpoint(experiment, &policy);
+            return greedy(policy);
+        }
+        Algorithm::Neat => {
+            let winner: neat::Genome = neat::evolve(
+                game.observation_space,
+                game.action_space,
+                &experiment.environment,
+                &experiment.neat,
+                experiment.seed,
+            );
+            let network = neat::FeedForward::from_genome(&winner);
+            return Policy::discrete(move |observation| network.choose(observation));
+        }
+        Algorithm::BehaviorCloning => {
+            let demonstrations: Vec<imitation::Demonstration> =
+                imitation::load_demonstrations(&experiment.demonstration_path).expect("Failed to load demonstrations");
+            let mut policy: model::Model = experiment.build_network(game.observation_space, game.action_space);
+            imitation::behavior_cloning(&mut policy, &demonstrations, &experiment.behavior_cloning, experiment.seed);
+            save_checkpoint(experiment, &policy);
+            return greedy(policy);
+        }
+        Algorithm::Dqfd => {
+            let demonstrations: Vec<imitation::Demonstration> =
+                imitation::load_demonstrations(&experiment.demonstration_path).expect("Failed to load demonstrations");
+            let mut agent: model::Model = experiment.build_network(game.observation_space, game.action_space);
+            train::train_from_demonstrations(game, &mut agent, &demonstrations, &experiment.train);
+            save_checkpoint(experiment, &agent);
+            return greedy(agent);
+        }
+        Algorithm::Cql | Algorithm::Bcq => {
+            // transitions dumped by an earlier Dqn run
+            let dataset = offline::Dataset::load(&experiment.train.dataset_path).expect("Failed to load dataset");
+            let constraint: offline::Constraint = if matches!(experiment.algorithm, Algorithm::Cql) {
+                offline::Constraint::Cql {
+                    alpha: experiment.offline.cql_alpha,
+                }
+            } else {
+                offline::Constraint::Bcq {
+                    threshold: experiment.offline.bcq_threshold,
+                    behavior: experiment.build_network(game.observation_space, game.action_space),
+                }
+            };
+            let mut agent = offline::OfflineAgent::new(
+                experiment.build_network(game.observation_space, game.action_space),
+                constraint,
+            );
+            offline::train(&mut agent, &dataset, &experiment.offline, experiment.seed);
+            if matches!(agent.constraint, offline::Constraint::Cql { .. }) {
+                save_checkpoint(experiment, &agent.q_network);
+            }
+            return Policy::discrete(move |observation| agent.greedy_action(observation));
+        }
+    };
+    let model: model::Model = experiment.build_network(
+        game.observation_space,
+        game.action_space * head.outputs_per_action(),
+    );
+    let mut agent = distributional::DistributionalAgent::new(model, head, game.action_space);
+    distributional::train(game, &mut agent, &experiment.train);
+    Policy::discrete(move |observation| agent.greedy_action(observation))
+}
+
+// Evaluation episodes are seeded from the environment seed, or train::SEED
+// for unseeded configs.
+fn evaluation_environment(experiment: &ExperimentConfig) -> EnvironmentConfig {
+    EnvironmentConfig {
+        seed: Some(experiment.environment.seed.unwrap_or(train::SEED)),
+        ..experiment.environment.clone()
+    }
+}
+
+fn evaluate(experiment: &ExperimentConfig, episodes: u64) {
+    let mut policy: model::Model = load_checkpoint(experiment);
+    let environment: EnvironmentConfig = evaluation_environment(experiment);
+    let mut observation: Array1<f32> = Array1::zeros(experiment.game().observation_space);
+    let scores: Vec<f32> = (0..episodes)
+        .map(|episode| {
+            let mut game: game::Game = environment.build(episode);
+            let mut score: f32 = 0.;
+            loop {
+                game.observe(&mut observation);
+                let (reward, finished) = game.step(model::argmax(policy.forward(&observation)));
+                score += reward;
+                if finished {
+                    check_trajectories(&game);
+                    return score;
+                }
+            }
+        })
+        .collect();
+    let mean: f32 = scores.iter().sum::<f32>() / scores.len() as f32;
+    let std: f32 = (scores.iter().map(|score| (score - mean).powi(2)).sum::<f32>() / scores.len() as f32).sqrt();
+    println!(
+        "{} episodes\tMean: {}\tStd: {}\tMin: {}\tMax: {}",
+        episodes,
+        mean,
+        std,
+        scores.iter().cloned().fold(f32::INFINITY, f32::min),
+        scores.iter().cloned().fold(f32::NEG_INFINITY, f32::max)
+    );
+}
+
+// The same episodes as the first ones of eval, written to the train
+// table's recording directory.
+fn record(experiment: &ExperimentConfig, episodes: u64) {
+    let mut policy: model::Model = load_checkpoint(experiment);
+    let environment: EnvironmentConfig = evaluation_environment(experiment);
+    let config: &RecordingConfig = &experiment.train.recording;
+    for episode in 0..episodes {
+        let path: PathBuf = config.episode_path(&format!("eval_{}", episode));
+        let mut game: game::Game = environment.build(episode);
+        let score: f32 = recording::record_episode(
+            &mut game,
+            |observation| model::argmax(policy.forward(observation)),
+            config,
+            &path,
+        )
+        .expect("Failed to record episode");
+        check_trajectories(&game);
+        println!("Episode {}\tScore: {}\tRecorded to {}", episode, score, path.display());
+    }
+}
+
+// Verifies the trajectory headlessly, exiting on divergence. Returns the game
+// it was verified on, with logging off, and the trajectory.
+fn replay(experiment: &ExperimentConfig, path: &Path) -> (game::Game, Trajectory) {
+    let trajectory: Trajectory = Trajectory::load(path).expect("Failed to load trajectory");
+    if trajectory.config_hash != experiment.environment.config_hash() {
+        eprintln!("The trajectory was logged with a different environment config");
+        process::exit(1);
+    }
+    let mut game: game::Game = experiment.game();
+    game.log_trajectories(None);
+    match trajectory::replay(&mut game, &trajectory) {
+        Ok(rewards) => println!(
+            "Replayed {} steps (score {}), every observation and reward matches",
+            rewards.len(),
+            rewards.iter().sum::<f32>()
+        ),
+        Err(divergence) => {
+            eprintln!("Replay diverged. {}", divergence);
+            process::exit(1);
+        }
+    }
+    (game, trajectory)
+}
+
+#[cfg(feature = "graphics")]
+async fn record_demonstrations(experiment: ExperimentConfig) {
+    let mut choices: Vec<(Array1<f32>, usize)> = vec![];
+    let rewards: Vec<f32> = game::run_game(experiment.game(), |observation| {
+        let action: usize = choose();
+        choices.push((observation.clone(), action));
+        action
+    })
+    .await;
+    let steps: usize = rewards.len();
+    let demonstrations: Vec<imitation::Demonstration> = choices
+        .into_iter()
+        .zip(rewards)
+        .enumerate()
+        .map(|(i, ((observation, action), reward))| imitation::Demonstration {
+            observation,
+            action,
+            reward,
+            done: i + 1 == steps,
+        })
+        .collect();
+    imitation::append_demonstrations(&experiment.demonstration_path, &demonstrations)
+        .expect("Failed to save demonstrations");
+}
+
+// Throughput of random simulation steps and of forward/backward passes
+// through the configured network.
+fn bench(experiment: &ExperimentConfig, iterations: usize) {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(experiment.seed);
+    let mut game: game::Game = experiment.game();
+    let start: Instant = Instant::now();
+    for _ in 0..iterations {
+        let (_, finished) = game.step(rng.random_range(0..game.action_space));
+        if finished {
+            game.reset();
+        }
+    }
+    let simulation: f64 = start.elapsed().as_secs_f64();
+    let mut network: model::Model = experiment.build_network(game.observation_space, game.action_space);
+    let mut observation: Array1<f32> = Array1::zeros(game.observation_space);
+    let start: Instant = Instant::now();
+    for _ in 0..iterations {
+        game.observe(&mut observation);
+        let derivative: Array1<f32> = network.forward(&observation).clone();
+        network.backprop(&observation, &derivative);
+        network.zero_gradients();
     }
+    let learning: f64 = start.elapsed().as_secs_f64();
+    check_trajectories(&game);
+    println!(
+        "Simulation: {:.0} steps/s\tNetwork ({} parameters): {:.0} updates/s",
+        iterations as f64 / simulation,
+        network.num_parameters(),
+        iterations as f64 / learning
+    );
 }
 
+// A fires the left engine, anything else the right one (the lander has no idle action)
+#[cfg(feature = "graphics")]
 fn choose() -> usize {
-    0
-    // if is_key_down(KeyCode::A) {
-    //     if is_key_down(KeyCode::W) { 5 } else { 1 }
-    // } else if is_key_down(KeyCode::D) {
-    //     if is_key_down(KeyCode::W) { 4 } else { 0 }
-    // } else if is_key_down(KeyCode::W) {
-    //     2
-    // } else {
-    //     3
-    // }
+    if is_key_down(KeyCode::A) { 1 } else { 0 }
 }
 
+#[cfg(feature = "graphics")]
 pub fn window_conf() -> Conf {
     Conf {
         window_title: "Lunar Lander".to_string(),
@@ -1,10 +1,67 @@
-use ndarray::{Array1, Array2};
+use std::f32::consts::{E, PI};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use dyn_clone::DynClone;
+use ndarray::{Array1, Array2, ArrayViewD, ArrayViewMutD};
 use ndarray_rand::RandomExt;
-use rand::{SeedableRng};
-use rand_distr::Normal;
+use rand::SeedableRng;
+use rand_distr::{Normal, Uniform};
+use serde::{Deserialize, Serialize};
 
 use crate::train;
 
+const NOISY_SIGMA_ZERO: f32 = 0.5;
+const LOG_STD_MIN: f32 = -20.;
+const LOG_STD_MAX: f32 = 2.;
+const SQUASH_EPSILON: f32 = 1e-6;
+// checkpoint header, followed by the parameter count (u64) and the flat
+// parameters (f32), little endian
+const CHECKPOINT_MAGIC: &[u8; 4] = b"RLMD";
+
+pub fn argmax(values: &Array1<f32>) -> usize {
+    values
+        .indexed_iter()
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+pub fn softmax(logits: &Array1<f32>) -> Array1<f32> {
+    let max: f32 = logits.fold(f32::NEG_INFINITY, |acc, &x| acc.max(x));
+    let exps: Array1<f32> = logits.mapv(|x| (x - max).exp());
+    let total: f32 = exps.sum();
+    exps / total
+}
+
+pub fn entropy(probabilities: &Array1<f32>) -> f32 {
+    -probabilities
+        .iter()
+        .map(|p| p * p.max(f32::EPSILON).ln())
+        .sum::<f32>()
+}
+
+pub trait Layer: DynClone {
+    fn forward(&mut self, prev_activation: &Array1<f32>);
+    fn compute_gradient(&mut self, prev_activation: &Array1<f32>, next_derivative: &Array1<f32>);
+    fn apply_gradient(&mut self, learning_rate: f32);
+    fn zero_gradient(&mut self);
+    fn activation(&self) -> &Array1<f32>;
+    fn prev_derivative(&self) -> &Array1<f32>;
+    // trainable tensors, in the same order as their gradients
+    fn parameters(&self) -> Vec<ArrayViewD<'_, f32>>;
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, f32>>;
+    fn gradients(&self) -> Vec<ArrayViewD<'_, f32>>;
+    fn resample_noise(&mut self) {}
+    fn set_training(&mut self, _training: bool) {}
+    fn is_noisy(&self) -> bool {
+        false
+    }
+}
+
+dyn_clone::clone_trait_object!(Layer);
+
 #[derive(Clone)]
 pub struct LinearLayer {
     pub weights: Array2<f32>,
@@ -18,8 +75,12 @@ pub struct LinearLayer {
 
 impl LinearLayer {
     pub fn new(inputs: usize, outputs: usize, relu: bool) -> Self {
+        Self::with_seed(inputs, outputs, relu, train::SEED)
+    }
+
+    pub fn with_seed(inputs: usize, outputs: usize, relu: bool, seed: u64) -> Self {
         let he_std: f32 = (2 as f32 / inputs as f32).sqrt();
-        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(train::SEED);
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
         Self {
             weights: Array2::random_using(
                 (outputs, inputs),
@@ -36,7 +97,7 @@ impl LinearLayer {
     }
 }
 
-impl LinearLayer {
+impl Layer for LinearLayer {
     fn forward(&mut self, prev_activation: &Array1<f32>) {
         self.activation = self.weights.dot(prev_activation) + &self.biases;
         if self.relu {
@@ -85,26 +146,679 @@ impl LinearLayer {
     fn prev_derivative(&self) -> &Array1<f32> {
         &self.prev_derivative
     }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![self.weights.view().into_dyn(), self.biases.view().into_dyn()]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, f32>> {
+        vec![
+            self.weights.view_mut().into_dyn(),
+            self.biases.view_mut().into_dyn(),
+        ]
+    }
+
+    fn gradients(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![
+            self.weight_gradient.view().into_dyn(),
+            self.bias_gradient.view().into_dyn(),
+        ]
+    }
+}
+
+// Factorised Gaussian NoisyNet layer (Fortunato et al. 2017): w = mu + sigma * eps
+#[derive(Clone)]
+pub struct NoisyLinear {
+    pub weight_mu: Array2<f32>,
+    pub weight_sigma: Array2<f32>,
+    pub weight_mu_gradient: Array2<f32>,
+    pub weight_sigma_gradient: Array2<f32>,
+    pub bias_mu: Array1<f32>,
+    pub bias_sigma: Array1<f32>,
+    pub bias_mu_gradient: Array1<f32>,
+    pub bias_sigma_gradient: Array1<f32>,
+    pub weight_epsilon: Array2<f32>,
+    pub bias_epsilon: Array1<f32>,
+    pub activation: Array1<f32>,
+    pub prev_derivative: Array1<f32>,
+    pub relu: bool,
+    pub training: bool,
+    rng: rand::rngs::StdRng,
+}
+
+impl NoisyLinear {
+    pub fn new(inputs: usize, outputs: usize, relu: bool) -> Self {
+        Self::with_seed(inputs, outputs, relu, train::SEED)
+    }
+
+    pub fn with_seed(inputs: usize, outputs: usize, relu: bool, seed: u64) -> Self {
+        let mu_range: f32 = 1. / (inputs as f32).sqrt();
+        let sigma_init: f32 = NOISY_SIGMA_ZERO / (inputs as f32).sqrt();
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut layer = Self {
+            weight_mu: Array2::random_using(
+                (outputs, inputs),
+                Uniform::new_inclusive(-mu_range, mu_range).unwrap(),
+                &mut rng,
+            ),
+            weight_sigma: Array2::from_elem((outputs, inputs), sigma_init),
+            weight_mu_gradient: Array2::zeros((outputs, inputs)),
+            weight_sigma_gradient: Array2::zeros((outputs, inputs)),
+            bias_mu: Array1::random_using(
+                outputs,
+                Uniform::new_inclusive(-mu_range, mu_range).unwrap(),
+                &mut rng,
+            ),
+            bias_sigma: Array1::from_elem(outputs, sigma_init),
+            bias_mu_gradient: Array1::zeros(outputs),
+            bias_sigma_gradient: Array1::zeros(outputs),
+            weight_epsilon: Array2::zeros((outputs, inputs)),
+            bias_epsilon: Array1::zeros(outputs),
+            activation: Array1::zeros(outputs),
+            prev_derivative: Array1::zeros(inputs),
+            relu,
+            training: true,
+            rng,
+        };
+        layer.resample_noise();
+        layer
+    }
+
+    // f(x) = sgn(x) * sqrt(|x|)
+    fn scaled_noise(&mut self, size: usize) -> Array1<f32> {
+        Array1::random_using(size, Normal::new(0., 1.).unwrap(), &mut self.rng)
+            .mapv(|x: f32| x.signum() * x.abs().sqrt())
+    }
+
+    fn effective_weights(&self) -> Array2<f32> {
+        if self.training {
+            &self.weight_mu + &(&self.weight_sigma * &self.weight_epsilon)
+        } else {
+            self.weight_mu.clone()
+        }
+    }
+}
+
+impl Layer for NoisyLinear {
+    fn forward(&mut self, prev_activation: &Array1<f32>) {
+        self.activation = self.effective_weights().dot(prev_activation);
+        if self.training {
+            self.activation += &(&self.bias_mu + &(&self.bias_sigma * &self.bias_epsilon));
+        } else {
+            self.activation += &self.bias_mu;
+        }
+        if self.relu {
+            self.activation.mapv_inplace(|x| x.max(0.));
+        }
+    }
+
+    fn compute_gradient(&mut self, prev_activation: &Array1<f32>, next_derivative: &Array1<f32>) {
+        let derivative: Array1<f32> = if self.relu {
+            self.activation.mapv(|x| if x > 0.0 { 1.0 } else { 0.0 }) * next_derivative
+        } else {
+            next_derivative.clone()
+        };
+        self.prev_derivative = self.effective_weights().t().dot(&derivative);
+        let outer: Array2<f32> = derivative
+            .view()
+            .insert_axis(ndarray::Axis(1))
+            .dot(&prev_activation.view().insert_axis(ndarray::Axis(0)));
+        self.weight_mu_gradient += &outer;
+        self.bias_mu_gradient += &derivative;
+        if self.training {
+            self.weight_sigma_gradient += &(outer * &self.weight_epsilon);
+            self.bias_sigma_gradient += &(derivative * &self.bias_epsilon);
+        }
+    }
+
+    fn apply_gradient(&mut self, learning_rate: f32) {
+        self.weight_mu.scaled_add(-learning_rate, &self.weight_mu_gradient);
+        self.weight_sigma.scaled_add(-learning_rate, &self.weight_sigma_gradient);
+        self.bias_mu.scaled_add(-learning_rate, &self.bias_mu_gradient);
+        self.bias_sigma.scaled_add(-learning_rate, &self.bias_sigma_gradient);
+        self.zero_gradient();
+    }
+
+    fn zero_gradient(&mut self) {
+        self.weight_mu_gradient.fill(0.);
+        self.weight_sigma_gradient.fill(0.);
+        self.bias_mu_gradient.fill(0.);
+        self.bias_sigma_gradient.fill(0.);
+    }
+
+    fn activation(&self) -> &Array1<f32> {
+        &self.activation
+    }
+
+    fn prev_derivative(&self) -> &Array1<f32> {
+        &self.prev_derivative
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![
+            self.weight_mu.view().into_dyn(),
+            self.weight_sigma.view().into_dyn(),
+            self.bias_mu.view().into_dyn(),
+            self.bias_sigma.view().into_dyn(),
+        ]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, f32>> {
+        vec![
+            self.weight_mu.view_mut().into_dyn(),
+            self.weight_sigma.view_mut().into_dyn(),
+            self.bias_mu.view_mut().into_dyn(),
+            self.bias_sigma.view_mut().into_dyn(),
+        ]
+    }
+
+    fn gradients(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![
+            self.weight_mu_gradient.view().into_dyn(),
+            self.weight_sigma_gradient.view().into_dyn(),
+            self.bias_mu_gradient.view().into_dyn(),
+            self.bias_sigma_gradient.view().into_dyn(),
+        ]
+    }
+
+    fn resample_noise(&mut self) {
+        let (outputs, inputs) = self.weight_mu.dim();
+        let input_noise: Array1<f32> = self.scaled_noise(inputs);
+        let output_noise: Array1<f32> = self.scaled_noise(outputs);
+        self.weight_epsilon = output_noise
+            .view()
+            .insert_axis(ndarray::Axis(1))
+            .dot(&input_noise.view().insert_axis(ndarray::Axis(0)));
+        self.bias_epsilon = output_noise;
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    fn is_noisy(&self) -> bool {
+        true
+    }
+}
+
+// A [channels, height, width] image flattened row by row, channel after
+// channel, the layout pixels::FrameStack produces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageShape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl ImageShape {
+    pub fn new(channels: usize, height: usize, width: usize) -> Self {
+        Self { channels, height, width }
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // (height, width) of the positions a kernel visits sliding over the
+    // padded image, None when it does not fit
+    pub fn window_output(&self, kernel: usize, stride: usize, padding: usize) -> Option<(usize, usize)> {
+        let (height, width) = (self.height + 2 * padding, self.width + 2 * padding);
+        if kernel == 0 || stride == 0 || kernel > height || kernel > width {
+            return None;
+        }
+        Some(((height - kernel) / stride + 1, (width - kernel) / stride + 1))
+    }
+}
+
+// Where a kernel slides over an image: the output shape and the mapping
+// between image pixels and im2col entries.
+#[derive(Clone, Copy, Debug)]
+struct Window {
+    input: ImageShape,
+    kernel: usize,
+    stride: usize,
+    padding: usize,
+    output_height: usize,
+    output_width: usize,
+}
+
+impl Window {
+    fn new(input: ImageShape, kernel: usize, stride: usize, padding: usize) -> Self {
+        assert!(kernel > 0 && stride > 0, "Kernel size and stride must be positive");
+        let (output_height, output_width) = input
+            .window_output(kernel, stride, padding)
+            .expect("Kernel larger than the padded input");
+        Self {
+            input,
+            kernel,
+            stride,
+            padding,
+            output_height,
+            output_width,
+        }
+    }
+
+    fn positions(&self) -> usize {
+        self.output_height * self.output_width
+    }
+
+    // Calls visit(row, column, pixel) for every im2col entry that falls on
+    // the image rather than the padding. Row (c, ky, kx) of the column matrix
+    // is channel c at kernel offset (ky, kx); column (y, x) is the output
+    // position.
+    fn for_each(&self, mut visit: impl FnMut(usize, usize, usize)) {
+        let ImageShape { channels, height, width } = self.input;
+        for c in 0..channels {
+            for ky in 0..self.kernel {
+                for kx in 0..self.kernel {
+                    let row: usize = (c * self.kernel + ky) * self.kernel + kx;
+                    for y in 0..self.output_height {
+                        let image_y: usize = y * self.stride + ky;
+                        if image_y < self.padding || image_y - self.padding >= height {
+                            continue;
+                        }
+                        for x in 0..self.output_width {
+                            let image_x: usize = x * self.stride + kx;
+                            if image_x < self.padding || image_x - self.padding >= width {
+                                continue;
+                            }
+                            let pixel: usize = (c * height + image_y - self.padding) * width + image_x - self.padding;
+                            visit(row, y * self.output_width + x, pixel);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Unrolls every kernel-sized patch into a column, so convolution becomes
+    // a single matrix product. Padding reads as zero.
+    fn im2col(&self, image: &Array1<f32>) -> Array2<f32> {
+        let mut columns: Array2<f32> =
+            Array2::zeros((self.input.channels * self.kernel * self.kernel, self.positions()));
+        self.for_each(|row, column, pixel| columns[[row, column]] = image[pixel]);
+        columns
+    }
+
+    // Adjoint of im2col: sums every column entry back onto its pixel.
+    fn col2im(&self, columns: &Array2<f32>) -> Array1<f32> {
+        let mut image: Array1<f32> = Array1::zeros(self.input.len());
+        self.for_each(|row, column, pixel| image[pixel] += columns[[row, column]]);
+        image
+    }
+}
+
+#[derive(Clone)]
+pub struct Conv2d {
+    // [output channels, input channels * kernel * kernel]
+    pub weights: Array2<f32>,
+    pub weight_gradient: Array2<f32>,
+    pub biases: Array1<f32>,
+    pub bias_gradient: Array1<f32>,
+    pub activation: Array1<f32>,
+    pub prev_derivative: Array1<f32>,
+    pub relu: bool,
+    window: Window,
+    // im2col of the last forward's input, which backprop's input is
+    columns: Array2<f32>,
+}
+
+impl Conv2d {
+    pub fn new(input: ImageShape, channels: usize, kernel: usize, stride: usize, padding: usize, relu: bool) -> Self {
+        Self::with_seed(input, channels, kernel, stride, padding, relu, train::SEED)
+    }
+
+    // He initialisation over the fan-in of one output, like LinearLayer
+    pub fn with_seed(
+        input: ImageShape,
+        channels: usize,
+        kernel: usize,
+        stride: usize,
+        padding: usize,
+        relu: bool,
+        seed: u64,
+    ) -> Self {
+        let window = Window::new(input, kernel, stride, padding);
+        let fan_in: usize = input.channels * kernel * kernel;
+        let he_std: f32 = (2. / fan_in as f32).sqrt();
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+        Self {
+            weights: Array2::random_using((channels, fan_in), Normal::new(0., he_std).unwrap(), &mut rng),
+            weight_gradient: Array2::zeros((channels, fan_in)),
+            biases: Array1::zeros(channels),
+            bias_gradient: Array1::zeros(channels),
+            activation: Array1::zeros(channels * window.positions()),
+            prev_derivative: Array1::zeros(input.len()),
+            relu,
+            window,
+            columns: Array2::zeros((fan_in, window.positions())),
+        }
+    }
+
+    pub fn output_shape(&self) -> ImageShape {
+        ImageShape::new(self.weights.nrows(), self.window.output_height, self.window.output_width)
+    }
+}
+
+impl Layer for Conv2d {
+    fn forward(&mut self, prev_activation: &Array1<f32>) {
+        self.columns = self.window.im2col(prev_activation);
+        let output: Array2<f32> = self.weights.dot(&self.columns) + self.biases.view().insert_axis(ndarray::Axis(1));
+        self.activation = output.into_shape_with_order(self.activation.len()).unwrap();
+        if self.relu {
+            self.activation.mapv_inplace(|x| x.max(0.));
+        }
+    }
+
+    // Like the ReLU mask, the columns come from the forward pass of the same
+    // input, so the input is not unrolled again.
+    fn compute_gradient(&mut self, _prev_activation: &Array1<f32>, next_derivative: &Array1<f32>) {
+        let derivative: Array1<f32> = if self.relu {
+            self.activation.mapv(|x| if x > 0.0 { 1.0 } else { 0.0 }) * next_derivative
+        } else {
+            next_derivative.clone()
+        };
+        let derivative: Array2<f32> = derivative
+            .into_shape_with_order((self.weights.nrows(), self.window.positions()))
+            .unwrap();
+        self.weight_gradient += &derivative.dot(&self.columns.t());
+        self.bias_gradient += &derivative.sum_axis(ndarray::Axis(1));
+        self.prev_derivative = self.window.col2im(&self.weights.t().dot(&derivative));
+    }
+
+    fn apply_gradient(&mut self, learning_rate: f32) {
+        self.weights.scaled_add(-learning_rate, &self.weight_gradient);
+        self.biases.scaled_add(-learning_rate, &self.bias_gradient);
+        self.zero_gradient();
+    }
+
+    fn zero_gradient(&mut self) {
+        self.weight_gradient.fill(0.);
+        self.bias_gradient.fill(0.);
+    }
+
+    fn activation(&self) -> &Array1<f32> {
+        &self.activation
+    }
+
+    fn prev_derivative(&self) -> &Array1<f32> {
+        &self.prev_derivative
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![self.weights.view().into_dyn(), self.biases.view().into_dyn()]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, f32>> {
+        vec![
+            self.weights.view_mut().into_dyn(),
+            self.biases.view_mut().into_dyn(),
+        ]
+    }
+
+    fn gradients(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![
+            self.weight_gradient.view().into_dyn(),
+            self.bias_gradient.view().into_dyn(),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+    Max,
+    Average,
+}
+
+// Pools every channel separately over size x size windows.
+#[derive(Clone)]
+pub struct Pool2d {
+    pub pooling: Pooling,
+    pub activation: Array1<f32>,
+    pub prev_derivative: Array1<f32>,
+    window: Window,
+    // the last forward's windows, see patches
+    patches: ndarray::Array3<f32>,
+}
+
+impl Pool2d {
+    pub fn new(input: ImageShape, pooling: Pooling, size: usize, stride: usize) -> Self {
+        let window = Window::new(input, size, stride, 0);
+        Self {
+            pooling,
+            activation: Array1::zeros(input.channels * window.positions()),
+            prev_derivative: Array1::zeros(input.len()),
+            window,
+            patches: ndarray::Array3::zeros((input.channels, size * size, window.positions())),
+        }
+    }
+
+    pub fn output_shape(&self) -> ImageShape {
+        ImageShape::new(self.window.input.channels, self.window.output_height, self.window.output_width)
+    }
+
+    // im2col's rows grouped by channel: [channels, size * size, positions]
+    fn patches(&self, prev_activation: &Array1<f32>) -> ndarray::Array3<f32> {
+        let area: usize = self.window.kernel * self.window.kernel;
+        self.window
+            .im2col(prev_activation)
+            .into_shape_with_order((self.window.input.channels, area, self.window.positions()))
+            .unwrap()
+    }
+}
+
+impl Layer for Pool2d {
+    fn forward(&mut self, prev_activation: &Array1<f32>) {
+        self.patches = self.patches(prev_activation);
+        let pooled: Array2<f32> = match self.pooling {
+            Pooling::Max => self
+                .patches
+                .fold_axis(ndarray::Axis(1), f32::NEG_INFINITY, |acc, &x| acc.max(x)),
+            Pooling::Average => self.patches.mean_axis(ndarray::Axis(1)).unwrap(),
+        };
+        self.activation = pooled.into_shape_with_order(self.activation.len()).unwrap();
+    }
+
+    // max pooling routes each derivative to its window's largest input in
+    // the forward pass, average pooling spreads it evenly
+    fn compute_gradient(&mut self, _prev_activation: &Array1<f32>, next_derivative: &Array1<f32>) {
+        let (channels, area, positions) = self.patches.dim();
+        let mut columns: ndarray::Array3<f32> = ndarray::Array3::zeros((channels, area, positions));
+        for c in 0..channels {
+            for p in 0..positions {
+                let derivative: f32 = next_derivative[c * positions + p];
+                match self.pooling {
+                    Pooling::Max => {
+                        let largest: usize = argmax(&self.patches.slice(ndarray::s![c, .., p]).to_owned());
+                        columns[[c, largest, p]] = derivative;
+                    }
+                    Pooling::Average => columns.slice_mut(ndarray::s![c, .., p]).fill(derivative / area as f32),
+                }
+            }
+        }
+        self.prev_derivative = self
+            .window
+            .col2im(&columns.into_shape_with_order((channels * area, positions)).unwrap());
+    }
+
+    fn apply_gradient(&mut self, _learning_rate: f32) {}
+
+    fn zero_gradient(&mut self) {}
+
+    fn activation(&self) -> &Array1<f32> {
+        &self.activation
+    }
+
+    fn prev_derivative(&self) -> &Array1<f32> {
+        &self.prev_derivative
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, f32>> {
+        vec![]
+    }
+
+    fn gradients(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![]
+    }
+}
+
+// Marks where images become plain vectors. Activations are already flat,
+// so it passes values and derivatives through unchanged.
+#[derive(Clone)]
+pub struct Flatten {
+    pub activation: Array1<f32>,
+    pub prev_derivative: Array1<f32>,
+}
+
+impl Flatten {
+    pub fn new(input: ImageShape) -> Self {
+        Self {
+            activation: Array1::zeros(input.len()),
+            prev_derivative: Array1::zeros(input.len()),
+        }
+    }
+}
+
+impl Layer for Flatten {
+    fn forward(&mut self, prev_activation: &Array1<f32>) {
+        self.activation.assign(prev_activation);
+    }
+
+    fn compute_gradient(&mut self, _prev_activation: &Array1<f32>, next_derivative: &Array1<f32>) {
+        self.prev_derivative.assign(next_derivative);
+    }
+
+    fn apply_gradient(&mut self, _learning_rate: f32) {}
+
+    fn zero_gradient(&mut self) {}
+
+    fn activation(&self) -> &Array1<f32> {
+        &self.activation
+    }
+
+    fn prev_derivative(&self) -> &Array1<f32> {
+        &self.prev_derivative
+    }
+
+    fn parameters(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<ArrayViewMutD<'_, f32>> {
+        vec![]
+    }
+
+    fn gradients(&self) -> Vec<ArrayViewD<'_, f32>> {
+        vec![]
+    }
 }
 
 #[derive(Clone)]
 pub struct Model {
-    pub layers: Vec<LinearLayer>,
+    pub layers: Vec<Box<dyn Layer>>,
     pub num_layers: usize,
+    // initialisation seed for layers added to this model
+    pub seed: u64,
 }
 
 impl Model {
     pub fn new() -> Self {
+        Self::with_seed(train::SEED)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
         Self {
             layers: vec![],
             num_layers: 0,
+            seed,
         }
     }
 
+    // model seed offset by the layer's position
+    fn layer_seed(&self) -> u64 {
+        self.seed + self.num_layers as u64
+    }
+
     pub fn add_layer(&mut self, input_size: usize, output_size: usize, relu: bool) -> () {
         self.layers
-            .push(LinearLayer::new(input_size, output_size, relu));
+            .push(Box::new(LinearLayer::with_seed(input_size, output_size, relu, self.seed)));
+        self.num_layers += 1;
+    }
+
+    // Each noisy layer gets its own seed, so their noise is uncorrelated.
+    pub fn add_noisy_layer(&mut self, input_size: usize, output_size: usize, relu: bool) {
+        let seed: u64 = self.layer_seed();
+        self.layers
+            .push(Box::new(NoisyLinear::with_seed(input_size, output_size, relu, seed)));
+        self.num_layers += 1;
+    }
+
+    // Convolution over images of the given shape. Returns the output's shape,
+    // the input of the next image layer.
+    pub fn add_conv_layer(
+        &mut self,
+        input: ImageShape,
+        channels: usize,
+        kernel: usize,
+        stride: usize,
+        padding: usize,
+        relu: bool,
+    ) -> ImageShape {
+        let layer = Conv2d::with_seed(input, channels, kernel, stride, padding, relu, self.layer_seed());
+        let output: ImageShape = layer.output_shape();
+        self.layers.push(Box::new(layer));
+        self.num_layers += 1;
+        output
+    }
+
+    pub fn add_pool_layer(&mut self, input: ImageShape, pooling: Pooling, size: usize, stride: usize) -> ImageShape {
+        let layer = Pool2d::new(input, pooling, size, stride);
+        let output: ImageShape = layer.output_shape();
+        self.layers.push(Box::new(layer));
+        self.num_layers += 1;
+        output
+    }
+
+    // Returns the input size of the linear layers that follow.
+    pub fn add_flatten_layer(&mut self, input: ImageShape) -> usize {
+        self.layers.push(Box::new(Flatten::new(input)));
         self.num_layers += 1;
+        input.len()
+    }
+
+    // activation of the output layer from the last forward call
+    pub fn output(&self) -> &Array1<f32> {
+        self.layers[self.num_layers - 1].activation()
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.layers[self.num_layers - 1].activation().len()
+    }
+
+    pub fn is_noisy(&self) -> bool {
+        self.layers.iter().any(|layer| layer.is_noisy())
+    }
+
+    pub fn resample_noise(&mut self) {
+        for layer in &mut self.layers {
+            layer.resample_noise();
+        }
+    }
+
+    // evaluation mode makes noisy layers deterministic (mean weights only)
+    pub fn set_training(&mut self, training: bool) {
+        for layer in &mut self.layers {
+            layer.set_training(training);
+        }
     }
 
     pub fn forward(&mut self, state: &Array1<f32>) -> &Array1<f32> {
@@ -119,22 +833,27 @@ impl Model {
     }
 
     pub fn backprop(&mut self, state: &Array1<f32>, loss_derivative: &Array1<f32>) {
-        let (before_layers, last_layer) = self.layers.split_at_mut(self.num_layers - 1);
-        last_layer[0].compute_gradient(
-            before_layers[before_layers.len() - 1].activation(),
-            loss_derivative,
-        );
-        for i in (1..self.layers.len() - 1).rev() {
+        let last: usize = self.layers.len() - 1;
+        for i in (0..self.layers.len()).rev() {
             let (before_layers, after_layers) = self.layers.split_at_mut(i);
             let (curr_layer, after_layers) = after_layers.split_at_mut(1);
-            curr_layer[0].compute_gradient(
-                before_layers[before_layers.len() - 1].activation(),
-                after_layers[0].prev_derivative(),
-            );
+            let prev_activation: &Array1<f32> = if i == 0 {
+                state
+            } else {
+                before_layers[i - 1].activation()
+            };
+            let next_derivative: &Array1<f32> = if i == last {
+                loss_derivative
+            } else {
+                after_layers[0].prev_derivative()
+            };
+            curr_layer[0].compute_gradient(prev_activation, next_derivative);
         }
-        let (first_layer, other_layers) = self.layers.split_at_mut(1);
-        first_layer[0].compute_gradient(&state, other_layers[0].prev_derivative());
-        // self.layers[0].compute_gradient(state, loss_derivative);
+    }
+
+    // derivative of the loss w.r.t. the input of the last backprop call
+    pub fn input_derivative(&self) -> &Array1<f32> {
+        self.layers[0].prev_derivative()
     }
 
     pub fn apply_gradients(&mut self, learning_rate: f32) {
@@ -142,4 +861,413 @@ impl Model {
             layer.apply_gradient(learning_rate);
         }
     }
+
+    // discards accumulated gradients, e.g. after backpropagating through a critic only for its input derivative
+    pub fn zero_gradients(&mut self) {
+        for layer in &mut self.layers {
+            layer.zero_gradient();
+        }
+    }
+
+    pub fn num_parameters(&self) -> usize {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.parameters())
+            .map(|parameter| parameter.len())
+            .sum()
+    }
+
+    // every layer's parameters, in layer order, concatenated into one vector
+    pub fn flat_parameters(&self) -> Array1<f32> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.parameters())
+            .flat_map(|parameter| parameter.iter().copied().collect::<Vec<f32>>())
+            .collect()
+    }
+
+    // inverse of flat_parameters
+    pub fn set_flat_parameters(&mut self, flat: &Array1<f32>) {
+        assert_eq!(flat.len(), self.num_parameters(), "Flat parameter vector has the wrong length");
+        let mut values = flat.iter();
+        for layer in &mut self.layers {
+            for mut parameter in layer.parameters_mut() {
+                parameter.iter_mut().for_each(|p| *p = *values.next().unwrap());
+            }
+        }
+    }
+
+    // Checkpoints only hold the parameters, the architecture comes from the
+    // experiment config the model was built from.
+    pub fn save_parameters(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&(self.num_parameters() as u64).to_le_bytes())?;
+        for value in self.flat_parameters() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    pub fn load_parameters(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header: [u8; 12] = [0; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != CHECKPOINT_MAGIC {
+            return Err(invalid("Not a model checkpoint"));
+        }
+        if u64::from_le_bytes(header[4..].try_into().unwrap()) != self.num_parameters() as u64 {
+            return Err(invalid("Checkpoint does not match the model's architecture"));
+        }
+        let mut bytes: Vec<u8> = vec![];
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() != 4 * self.num_parameters() {
+            return Err(invalid("Truncated model checkpoint"));
+        }
+        let flat: Array1<f32> = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        self.set_flat_parameters(&flat);
+        Ok(())
+    }
+
+    // Polyak averaging towards source: theta <- tau * theta_source + (1 - tau) * theta
+    pub fn soft_update(&mut self, source: &Model, tau: f32) {
+        for (layer, source_layer) in self.layers.iter_mut().zip(source.layers.iter()) {
+            for (mut parameter, source_parameter) in layer
+                .parameters_mut()
+                .into_iter()
+                .zip(source_layer.parameters())
+            {
+                parameter.zip_mut_with(&source_parameter, |p, &s| *p = tau * s + (1. - tau) * *p);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum LogStd {
+    // the model outputs the means followed by the log-stds
+    StateDependent,
+    // a learnable vector shared by every state
+    Parameter {
+        values: Array1<f32>,
+        gradient: Array1<f32>,
+    },
+}
+
+impl LogStd {
+    pub fn parameter(action_dims: usize, initial: f32) -> Self {
+        LogStd::Parameter {
+            values: Array1::from_elem(action_dims, initial),
+            gradient: Array1::zeros(action_dims),
+        }
+    }
+}
+
+pub struct GaussianSample {
+    // tanh(pre_tanh) when squashed, pre_tanh otherwise
+    pub action: Array1<f32>,
+    pub pre_tanh: Array1<f32>,
+    // standard normal noise used by the reparameterization u = mean + std * noise
+    pub noise: Array1<f32>,
+    pub log_prob: f32,
+}
+
+// Diagonal Gaussian policy over continuous actions, optionally squashed into
+// (-1, 1) with tanh. Gradients are pushed back through the wrapped model.
+#[derive(Clone)]
+pub struct GaussianPolicy {
+    pub model: Model,
+    pub action_dims: usize,
+    pub log_std: LogStd,
+    pub squash: bool,
+}
+
+impl GaussianPolicy {
+    pub fn new(model: Model, action_dims: usize, log_std: LogStd, squash: bool) -> Self {
+        let expected: usize = match log_std {
+            LogStd::StateDependent => 2 * action_dims,
+            LogStd::Parameter { .. } => action_dims,
+        };
+        assert_eq!(model.output_size(), expected, "Model output does not match the log-std layout");
+        Self {
+            model,
+            action_dims,
+            log_std,
+            squash,
+        }
+    }
+
+    // (mean, log_std) of the pre-squash Gaussian at this state
+    pub fn distribution(&mut self, state: &Array1<f32>) -> (Array1<f32>, Array1<f32>) {
+        self.model.forward(state);
+        self.last_distribution()
+    }
+
+    fn last_distribution(&self) -> (Array1<f32>, Array1<f32>) {
+        let output: &Array1<f32> = self.model.output();
+        let mean: Array1<f32> = output.slice(ndarray::s![..self.action_dims]).to_owned();
+        let log_std: Array1<f32> = match &self.log_std {
+            LogStd::StateDependent => output.slice(ndarray::s![self.action_dims..]).to_owned(),
+            LogStd::Parameter { values, .. } => values.clone(),
+        };
+        (mean, log_std.mapv(|l| l.clamp(LOG_STD_MIN, LOG_STD_MAX)))
+    }
+
+    fn gaussian_log_prob(pre_tanh: &Array1<f32>, mean: &Array1<f32>, log_std: &Array1<f32>) -> f32 {
+        let mut log_prob: f32 = 0.;
+        for i in 0..pre_tanh.len() {
+            let z: f32 = (pre_tanh[i] - mean[i]) / log_std[i].exp();
+            log_prob += -0.5 * z * z - log_std[i] - 0.5 * (2. * PI).ln();
+        }
+        log_prob
+    }
+
+    // log |det da/du| of the tanh squashing, subtracted from the Gaussian log-prob
+    fn squash_correction(action: &Array1<f32>) -> f32 {
+        action.iter().map(|a| (1. - a * a + SQUASH_EPSILON).ln()).sum()
+    }
+
+    pub fn sample(&mut self, state: &Array1<f32>, rng: &mut rand::rngs::StdRng) -> GaussianSample {
+        let (mean, log_std) = self.distribution(state);
+        let noise: Array1<f32> = Array1::random_using(self.action_dims, Normal::new(0., 1.).unwrap(), rng);
+        let pre_tanh: Array1<f32> = &mean + &(log_std.mapv(f32::exp) * &noise);
+        let mut log_prob: f32 = Self::gaussian_log_prob(&pre_tanh, &mean, &log_std);
+        let action: Array1<f32> = if self.squash {
+            let squashed: Array1<f32> = pre_tanh.mapv(f32::tanh);
+            log_prob -= Self::squash_correction(&squashed);
+            squashed
+        } else {
+            pre_tanh.clone()
+        };
+        GaussianSample {
+            action,
+            pre_tanh,
+            noise,
+            log_prob,
+        }
+    }
+
+    pub fn deterministic_action(&mut self, state: &Array1<f32>) -> Array1<f32> {
+        let (mean, _) = self.distribution(state);
+        if self.squash { mean.mapv(f32::tanh) } else { mean }
+    }
+
+    // Log-probability of an already taken action, forwarding the state. Squashed
+    // actions are mapped back through atanh.
+    pub fn log_prob(&mut self, state: &Array1<f32>, action: &Array1<f32>) -> f32 {
+        let (mean, log_std) = self.distribution(state);
+        if self.squash {
+            let clipped: Array1<f32> = action.mapv(|a| a.clamp(-1. + SQUASH_EPSILON, 1. - SQUASH_EPSILON));
+            Self::gaussian_log_prob(&clipped.mapv(f32::atanh), &mean, &log_std)
+                - Self::squash_correction(&clipped)
+        } else {
+            Self::gaussian_log_prob(action, &mean, &log_std)
+        }
+    }
+
+    // Entropy of the pre-squash Gaussian (the squashed one has no closed form).
+    pub fn entropy(&self) -> f32 {
+        let (_, log_std) = self.last_distribution();
+        log_std.iter().map(|l| l + 0.5 * (2. * PI * E).ln()).sum()
+    }
+
+    // Score-function gradient for a fixed action, given dL/dlog_prob and
+    // dL/dentropy. Must follow log_prob() for the same state and action.
+    pub fn backprop_log_prob(
+        &mut self,
+        state: &Array1<f32>,
+        action: &Array1<f32>,
+        log_prob_derivative: f32,
+        entropy_derivative: f32,
+    ) {
+        let (mean, log_std) = self.last_distribution();
+        let pre_tanh: Array1<f32> = if self.squash {
+            action.mapv(|a| a.clamp(-1. + SQUASH_EPSILON, 1. - SQUASH_EPSILON).atanh())
+        } else {
+            action.clone()
+        };
+        let variance: Array1<f32> = log_std.mapv(|l| (2. * l).exp());
+        let error: Array1<f32> = &pre_tanh - &mean;
+        // dlogp/dmu = (u - mu) / sigma^2, dlogp/dlog_sigma = (u - mu)^2 / sigma^2 - 1, dH/dlog_sigma = 1
+        let mean_derivative: Array1<f32> = &error / &variance * log_prob_derivative;
+        let log_std_derivative: Array1<f32> =
+            (&error * &error / &variance - 1.) * log_prob_derivative + entropy_derivative;
+        self.backprop_distribution(state, &mean_derivative, &log_std_derivative);
+    }
+
+    // Pathwise gradient through a reparameterized sample, given dL/daction and
+    // dL/dlog_prob. Must follow sample() for the same state.
+    pub fn backprop_reparameterized(
+        &mut self,
+        state: &Array1<f32>,
+        sample: &GaussianSample,
+        action_derivative: &Array1<f32>,
+        log_prob_derivative: f32,
+    ) {
+        let (_, log_std) = self.last_distribution();
+        // dL/du; with squashing da/du = 1 - a^2 and d(-log(1 - a^2))/du = 2a
+        let pre_tanh_derivative: Array1<f32> = if self.squash {
+            action_derivative * &sample.action.mapv(|a| 1. - a * a)
+                + sample.action.mapv(|a| 2. * a) * log_prob_derivative
+        } else {
+            action_derivative.clone()
+        };
+        // the Gaussian term -noise^2 / 2 - log_sigma only depends on log_sigma
+        let log_std_derivative: Array1<f32> =
+            &pre_tanh_derivative * &(log_std.mapv(f32::exp) * &sample.noise) - log_prob_derivative;
+        self.backprop_distribution(state, &pre_tanh_derivative, &log_std_derivative);
+    }
+
+    fn backprop_distribution(
+        &mut self,
+        state: &Array1<f32>,
+        mean_derivative: &Array1<f32>,
+        log_std_derivative: &Array1<f32>,
+    ) {
+        match &mut self.log_std {
+            LogStd::StateDependent => {
+                let raw_log_std: Array1<f32> =
+                    self.model.output().slice(ndarray::s![self.action_dims..]).to_owned();
+                let mut derivative: Array1<f32> = Array1::zeros(2 * self.action_dims);
+                for i in 0..self.action_dims {
+                    derivative[i] = mean_derivative[i];
+                    // no gradient flows through the clamp once it saturates
+                    if (LOG_STD_MIN..=LOG_STD_MAX).contains(&raw_log_std[i]) {
+                        derivative[self.action_dims + i] = log_std_derivative[i];
+                    }
+                }
+                self.model.backprop(state, &derivative);
+            }
+            LogStd::Parameter { gradient, .. } => {
+                *gradient += log_std_derivative;
+                self.model.backprop(state, mean_derivative);
+            }
+        }
+    }
+
+    pub fn apply_gradients(&mut self, learning_rate: f32) {
+        self.model.apply_gradients(learning_rate);
+        if let LogStd::Parameter { values, gradient } = &mut self.log_std {
+            values.scaled_add(-learning_rate, gradient);
+            gradient.fill(0.);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every layer's gradients, flattened in the order of flat_parameters
+    fn flat_gradients(model: &Model) -> Array1<f32> {
+        model
+            .layers
+            .iter()
+            .flat_map(|layer| layer.gradients())
+            .flat_map(|gradient| gradient.iter().copied().collect::<Vec<f32>>())
+            .collect()
+    }
+
+    // half the squared distance to target
+    fn loss(model: &mut Model, input: &Array1<f32>, target: &Array1<f32>) -> f32 {
+        (model.forward(input) - target).mapv(|x| x * x).sum() / 2.
+    }
+
+    // Compares backprop's parameter and input derivatives with central
+    // differences of the loss.
+    fn check_gradients(mut model: Model, input: Array1<f32>) {
+        let target: Array1<f32> = Array1::linspace(-1., 1., model.forward(&input).len());
+        let derivative: Array1<f32> = model.forward(&input) - &target;
+        model.backprop(&input, &derivative);
+        let analytic: Array1<f32> = flat_gradients(&model);
+        let input_derivative: Array1<f32> = model.input_derivative().clone();
+        let epsilon: f32 = 1e-2;
+        let close = |analytic: f32, numeric: f32| (analytic - numeric).abs() <= 1e-2 * analytic.abs().max(numeric.abs()).max(1.);
+        let parameters: Array1<f32> = model.flat_parameters();
+        for i in 0..parameters.len() {
+            let mut shifted: Array1<f32> = parameters.clone();
+            shifted[i] += epsilon;
+            model.set_flat_parameters(&shifted);
+            let above: f32 = loss(&mut model, &input, &target);
+            shifted[i] -= 2. * epsilon;
+            model.set_flat_parameters(&shifted);
+            let below: f32 = loss(&mut model, &input, &target);
+            let numeric: f32 = (above - below) / (2. * epsilon);
+            assert!(close(analytic[i], numeric), "parameter {}: backprop {}, numeric {}", i, analytic[i], numeric);
+        }
+        model.set_flat_parameters(&parameters);
+        for i in 0..input.len() {
+            let mut shifted: Array1<f32> = input.clone();
+            shifted[i] += epsilon;
+            let above: f32 = loss(&mut model, &shifted, &target);
+            shifted[i] -= 2. * epsilon;
+            let below: f32 = loss(&mut model, &shifted, &target);
+            let numeric: f32 = (above - below) / (2. * epsilon);
+            assert!(close(input_derivative[i], numeric), "input {}: backprop {}, numeric {}", i, input_derivative[i], numeric);
+        }
+    }
+
+    fn image(shape: ImageShape) -> Array1<f32> {
+        Array1::from_shape_fn(shape.len(), |i| ((i * 7919) % 101) as f32 / 50. - 1.)
+    }
+
+    #[test]
+    fn conv_and_average_pool_gradients_match_finite_differences() {
+        let input = ImageShape::new(2, 6, 5);
+        let mut model = Model::new();
+        let shape: ImageShape = model.add_conv_layer(input, 3, 3, 1, 1, false);
+        let shape: ImageShape = model.add_pool_layer(shape, Pooling::Average, 2, 2);
+        let shape: ImageShape = model.add_conv_layer(shape, 2, 2, 1, 0, false);
+        let inputs: usize = model.add_flatten_layer(shape);
+        model.add_layer(inputs, 3, false);
+        check_gradients(model, image(input));
+    }
+
+    // No ReLU input or pooling tie of this image lies within epsilon of a
+    // kink, where finite differences would straddle two slopes.
+    #[test]
+    fn conv_relu_and_max_pool_gradients_match_finite_differences() {
+        let input = ImageShape::new(1, 7, 7);
+        let mut model = Model::new();
+        let shape: ImageShape = model.add_conv_layer(input, 2, 3, 2, 1, true);
+        let shape: ImageShape = model.add_pool_layer(shape, Pooling::Max, 2, 1);
+        let inputs: usize = model.add_flatten_layer(shape);
+        model.add_layer(inputs, 2, false);
+        check_gradients(model, image(input));
+    }
+
+    // a squashed policy with mean 0 and std 1 at every state
+    fn standard_squashed_policy() -> GaussianPolicy {
+        let mut model = Model::new();
+        model.add_layer(1, 2, false);
+        model.set_flat_parameters(&Array1::zeros(model.num_parameters()));
+        GaussianPolicy::new(model, 1, LogStd::StateDependent, true)
+    }
+
+    #[test]
+    fn squashed_log_prob_applies_the_tanh_change_of_variables() {
+        let mut policy: GaussianPolicy = standard_squashed_policy();
+        let action: f32 = 0.5;
+        let expected: f32 = -0.5 * action.atanh().powi(2) - 0.5 * (2. * PI).ln() - (1. - action * action).ln();
+        let log_prob: f32 = policy.log_prob(&Array1::from(vec![0.3]), &Array1::from(vec![action]));
+        assert!((log_prob - expected).abs() < 1e-4, "{} != {}", log_prob, expected);
+    }
+
+    #[test]
+    fn sampled_log_prob_matches_the_log_prob_of_the_squashed_action() {
+        let mut policy: GaussianPolicy = standard_squashed_policy();
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(train::SEED);
+        let state: Array1<f32> = Array1::from(vec![0.3]);
+        for _ in 0..20 {
+            let sample: GaussianSample = policy.sample(&state, &mut rng);
+            assert!(sample.action[0].abs() < 1.);
+            assert_eq!(sample.action[0], sample.pre_tanh[0].tanh());
+            let log_prob: f32 = policy.log_prob(&state, &sample.action);
+            assert!((sample.log_prob - log_prob).abs() < 1e-3, "{} != {}", sample.log_prob, log_prob);
+        }
+    }
 }
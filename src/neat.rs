@@ -0,0 +1,763 @@
+use std::collections::{HashMap, HashSet};
+
+use ndarray::Array1;
+use rand::seq::IndexedRandom;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+use crate::experiment::EnvironmentConfig;
+use crate::{evolution, model};
+
+// compatibility distance: c1 * excess / N + c2 * disjoint / N + c3 * mean weight difference
+const EXCESS_COEFFICIENT: f32 = 1.0;
+const DISJOINT_COEFFICIENT: f32 = 1.0;
+const WEIGHT_COEFFICIENT: f32 = 0.4;
+// species at least this large keep their champion unchanged
+const ELITISM_MIN_SPECIES_SIZE: usize = 5;
+const DISABLED_GENE_PROBABILITY: f32 = 0.75;
+const WEIGHT_REPLACE_PROBABILITY: f32 = 0.1;
+const ADD_CONNECTION_ATTEMPTS: usize = 20;
+
+// Hyperparameters of evolve, the neat table of an experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NeatConfig {
+    pub generations: u16,
+    pub population: usize,
+    // seeded episodes each genome is scored on
+    pub evaluation_episodes: u64,
+    pub compatibility_threshold: f32,
+    // generations without improvement before a species is dropped
+    pub stagnation_limit: u16,
+    // fraction of each species allowed to reproduce
+    pub survival_fraction: f32,
+    pub crossover_probability: f32,
+    pub weight_mutation_probability: f32,
+    pub weight_perturbation_std: f32,
+    pub add_connection_probability: f32,
+    pub add_node_probability: f32,
+}
+
+impl Default for NeatConfig {
+    fn default() -> Self {
+        Self {
+            generations: 50,
+            population: 64,
+            evaluation_episodes: 4,
+            compatibility_threshold: 3.0,
+            stagnation_limit: 15,
+            survival_fraction: 0.2,
+            crossover_probability: 0.75,
+            weight_mutation_probability: 0.8,
+            weight_perturbation_std: 0.5,
+            add_connection_probability: 0.05,
+            add_node_probability: 0.03,
+        }
+    }
+}
+
+impl NeatConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.generations == 0 || self.population == 0 || self.evaluation_episodes == 0 {
+            return Err("generations, population and evaluation_episodes must be positive".to_string());
+        }
+        if !(self.compatibility_threshold > 0. && self.compatibility_threshold.is_finite()) {
+            return Err(format!(
+                "compatibility_threshold must be positive, got {}",
+                self.compatibility_threshold
+            ));
+        }
+        if !(self.survival_fraction > 0. && self.survival_fraction <= 1.) {
+            return Err(format!("survival_fraction must be in (0, 1], got {}", self.survival_fraction));
+        }
+        for (name, probability) in [
+            ("crossover_probability", self.crossover_probability),
+            ("weight_mutation_probability", self.weight_mutation_probability),
+            ("add_connection_probability", self.add_connection_probability),
+            ("add_node_probability", self.add_node_probability),
+        ] {
+            if !(0. ..=1.).contains(&probability) {
+                return Err(format!("{} must be in [0, 1], got {}", name, probability));
+            }
+        }
+        if !(self.weight_perturbation_std >= 0. && self.weight_perturbation_std.is_finite()) {
+            return Err(format!(
+                "weight_perturbation_std must not be negative, got {}",
+                self.weight_perturbation_std
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeKind {
+    Input,
+    Bias,
+    Hidden,
+    Output,
+}
+
+#[derive(Clone, Debug)]
+pub struct NodeGene {
+    pub id: usize,
+    pub kind: NodeKind,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionGene {
+    pub input: usize,
+    pub output: usize,
+    pub weight: f32,
+    pub enabled: bool,
+    pub innovation: usize,
+}
+
+// Hands out historical markings so that the same structural mutation gets the
+// same innovation number (and split node id) everywhere in the population.
+pub struct InnovationTracker {
+    next_innovation: usize,
+    next_node: usize,
+    connections: HashMap<(usize, usize), usize>,
+    splits: HashMap<usize, usize>,
+}
+
+impl InnovationTracker {
+    pub fn new(first_hidden_node: usize) -> Self {
+        Self {
+            next_innovation: 0,
+            next_node: first_hidden_node,
+            connections: HashMap::new(),
+            splits: HashMap::new(),
+        }
+    }
+
+    pub fn connection(&mut self, input: usize, output: usize) -> usize {
+        let next: &mut usize = &mut self.next_innovation;
+        *self.connections.entry((input, output)).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+
+    // node id for splitting the given connection innovation
+    pub fn split(&mut self, innovation: usize) -> usize {
+        let next: &mut usize = &mut self.next_node;
+        *self.splits.entry(innovation).or_insert_with(|| {
+            *next += 1;
+            *next - 1
+        })
+    }
+
+    pub fn fresh_node(&mut self) -> usize {
+        self.next_node += 1;
+        self.next_node - 1
+    }
+}
+
+// Node ids: inputs first, then the bias node, then outputs, then hidden nodes.
+// Connections are kept sorted by innovation number.
+#[derive(Clone, Debug)]
+pub struct Genome {
+    pub nodes: Vec<NodeGene>,
+    pub connections: Vec<ConnectionGene>,
+    pub fitness: f32,
+}
+
+impl Genome {
+    // inputs and bias fully connected to the outputs with random weights
+    pub fn minimal(inputs: usize, outputs: usize, tracker: &mut InnovationTracker, rng: &mut rand::rngs::StdRng) -> Self {
+        let mut nodes: Vec<NodeGene> = (0..inputs)
+            .map(|id| NodeGene {
+                id,
+                kind: NodeKind::Input,
+            })
+            .collect();
+        nodes.push(NodeGene {
+            id: inputs,
+            kind: NodeKind::Bias,
+        });
+        nodes.extend((0..outputs).map(|i| NodeGene {
+            id: inputs + 1 + i,
+            kind: NodeKind::Output,
+        }));
+        let mut connections: Vec<ConnectionGene> = vec![];
+        for input in 0..=inputs {
+            for output in inputs + 1..inputs + 1 + outputs {
+                connections.push(ConnectionGene {
+                    input,
+                    output,
+                    weight: Normal::new(0., 1.).unwrap().sample(rng),
+                    enabled: true,
+                    innovation: tracker.connection(input, output),
+                });
+            }
+        }
+        connections.sort_by_key(|connection| connection.innovation);
+        Self {
+            nodes,
+            connections,
+            fitness: f32::NEG_INFINITY,
+        }
+    }
+
+    fn node_kind(&self, id: usize) -> NodeKind {
+        self.nodes.iter().find(|node| node.id == id).unwrap().kind
+    }
+
+    fn insert_connection(&mut self, connection: ConnectionGene) {
+        let position: usize = self
+            .connections
+            .partition_point(|existing| existing.innovation < connection.innovation);
+        self.connections.insert(position, connection);
+    }
+
+    // whether `to` can already reach `from`, so from -> to would close a loop
+    fn creates_cycle(&self, from: usize, to: usize) -> bool {
+        let mut stack: Vec<usize> = vec![to];
+        let mut seen: HashSet<usize> = HashSet::new();
+        while let Some(node) = stack.pop() {
+            if node == from {
+                return true;
+            }
+            if seen.insert(node) {
+                stack.extend(
+                    self.connections
+                        .iter()
+                        .filter(|connection| connection.input == node)
+                        .map(|connection| connection.output),
+                );
+            }
+        }
+        false
+    }
+
+    fn mutate_weights(&mut self, perturbation_std: f32, rng: &mut rand::rngs::StdRng) {
+        for connection in &mut self.connections {
+            if rng.random::<f32>() < WEIGHT_REPLACE_PROBABILITY {
+                connection.weight = Normal::new(0., 1.).unwrap().sample(rng);
+            } else {
+                connection.weight += Normal::new(0., perturbation_std).unwrap().sample(rng);
+            }
+        }
+    }
+
+    // adds a feedforward connection between two previously unconnected nodes
+    fn mutate_add_connection(&mut self, tracker: &mut InnovationTracker, rng: &mut rand::rngs::StdRng) {
+        for _ in 0..ADD_CONNECTION_ATTEMPTS {
+            let from: &NodeGene = self.nodes.choose(rng).unwrap();
+            let to: &NodeGene = self.nodes.choose(rng).unwrap();
+            let (from, to) = (from.id, to.id);
+            if from == to
+                || self.node_kind(from) == NodeKind::Output
+                || matches!(self.node_kind(to), NodeKind::Input | NodeKind::Bias)
+                || self
+                    .connections
+                    .iter()
+                    .any(|connection| connection.input == from && connection.output == to)
+                || self.creates_cycle(from, to)
+            {
+                continue;
+            }
+            let innovation: usize = tracker.connection(from, to);
+            self.insert_connection(ConnectionGene {
+                input: from,
+                output: to,
+                weight: Normal::new(0., 1.).unwrap().sample(rng),
+                enabled: true,
+                innovation,
+            });
+            return;
+        }
+    }
+
+    // splits an enabled connection a -> b into a -> new (weight 1) and new -> b (old weight)
+    fn mutate_add_node(&mut self, tracker: &mut InnovationTracker, rng: &mut rand::rngs::StdRng) {
+        let enabled: Vec<usize> = (0..self.connections.len())
+            .filter(|&i| self.connections[i].enabled)
+            .collect();
+        let Some(&index) = enabled.choose(rng) else {
+            return;
+        };
+        self.connections[index].enabled = false;
+        let old: ConnectionGene = self.connections[index].clone();
+        let mut node: usize = tracker.split(old.innovation);
+        if self.nodes.iter().any(|existing| existing.id == node) {
+            node = tracker.fresh_node();
+        }
+        self.nodes.push(NodeGene {
+            id: node,
+            kind: NodeKind::Hidden,
+        });
+        let incoming: usize = tracker.connection(old.input, node);
+        let outgoing: usize = tracker.connection(node, old.output);
+        self.insert_connection(ConnectionGene {
+            input: old.input,
+            output: node,
+            weight: 1.,
+            enabled: true,
+            innovation: incoming,
+        });
+        self.insert_connection(ConnectionGene {
+            input: node,
+            output: old.output,
+            weight: old.weight,
+            enabled: true,
+            innovation: outgoing,
+        });
+    }
+
+    pub fn mutate(&mut self, tracker: &mut InnovationTracker, config: &NeatConfig, rng: &mut rand::rngs::StdRng) {
+        if rng.random::<f32>() < config.weight_mutation_probability {
+            self.mutate_weights(config.weight_perturbation_std, rng);
+        }
+        if rng.random::<f32>() < config.add_connection_probability {
+            self.mutate_add_connection(tracker, rng);
+        }
+        if rng.random::<f32>() < config.add_node_probability {
+            self.mutate_add_node(tracker, rng);
+        }
+    }
+
+    // Matching genes are inherited at random, disjoint and excess genes from
+    // the fitter parent (self). Genes disabled in either parent are likely to
+    // stay disabled.
+    pub fn crossover(&self, other: &Genome, rng: &mut rand::rngs::StdRng) -> Genome {
+        let other_genes: HashMap<usize, &ConnectionGene> = other
+            .connections
+            .iter()
+            .map(|connection| (connection.innovation, connection))
+            .collect();
+        let mut connections: Vec<ConnectionGene> = vec![];
+        for gene in &self.connections {
+            let mut child: ConnectionGene = match other_genes.get(&gene.innovation) {
+                Some(matching) if rng.random::<bool>() => (*matching).clone(),
+                _ => gene.clone(),
+            };
+            if let Some(matching) = other_genes.get(&gene.innovation)
+                && (!gene.enabled || !matching.enabled)
+            {
+                child.enabled = rng.random::<f32>() >= DISABLED_GENE_PROBABILITY;
+            }
+            connections.push(child);
+        }
+        let mut child = Genome {
+            nodes: self.nodes.clone(),
+            connections,
+            fitness: f32::NEG_INFINITY,
+        };
+        // re-enabling genes may close a loop through the other parent's topology
+        for i in 0..child.connections.len() {
+            let (input, output) = (child.connections[i].input, child.connections[i].output);
+            if child.connections[i].enabled {
+                child.connections[i].enabled = false;
+                child.connections[i].enabled = !child.enabled_path(output, input);
+            }
+        }
+        child
+    }
+
+    fn enabled_path(&self, from: usize, to: usize) -> bool {
+        let mut stack: Vec<usize> = vec![from];
+        let mut seen: HashSet<usize> = HashSet::new();
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            if seen.insert(node) {
+                stack.extend(
+                    self.connections
+                        .iter()
+                        .filter(|connection| connection.enabled && connection.input == node)
+                        .map(|connection| connection.output),
+                );
+            }
+        }
+        false
+    }
+
+    pub fn compatibility_distance(&self, other: &Genome) -> f32 {
+        let own: HashMap<usize, f32> = self
+            .connections
+            .iter()
+            .map(|connection| (connection.innovation, connection.weight))
+            .collect();
+        let theirs: HashMap<usize, f32> = other
+            .connections
+            .iter()
+            .map(|connection| (connection.innovation, connection.weight))
+            .collect();
+        let own_max: usize = self.connections.last().map_or(0, |c| c.innovation);
+        let their_max: usize = other.connections.last().map_or(0, |c| c.innovation);
+        let (mut excess, mut disjoint, mut matching, mut weight_difference) = (0., 0., 0., 0.);
+        for (innovation, weight) in &own {
+            match theirs.get(innovation) {
+                Some(other_weight) => {
+                    matching += 1.;
+                    weight_difference += (weight - other_weight).abs();
+                }
+                None if *innovation > their_max => excess += 1.,
+                None => disjoint += 1.,
+            }
+        }
+        for innovation in theirs.keys().filter(|innovation| !own.contains_key(innovation)) {
+            if *innovation > own_max {
+                excess += 1.;
+            } else {
+                disjoint += 1.;
+            }
+        }
+        let genes: f32 = self.connections.len().max(other.connections.len()).max(1) as f32;
+        EXCESS_COEFFICIENT * excess / genes
+            + DISJOINT_COEFFICIENT * disjoint / genes
+            + WEIGHT_COEFFICIENT * if matching > 0. { weight_difference / matching } else { 0. }
+    }
+}
+
+// Evaluator for a genome's enabled connections. Hidden nodes use tanh, outputs
+// are linear so their argmax is the chosen action.
+#[derive(Clone, Debug)]
+pub struct FeedForward {
+    inputs: usize,
+    outputs: Vec<usize>,
+    // (node, incoming (source, weight) pairs) in topological order
+    order: Vec<(usize, Vec<(usize, f32)>)>,
+    hidden: HashSet<usize>,
+}
+
+impl FeedForward {
+    pub fn from_genome(genome: &Genome) -> Self {
+        let mut incoming: HashMap<usize, Vec<(usize, f32)>> = HashMap::new();
+        for connection in genome.connections.iter().filter(|connection| connection.enabled) {
+            incoming
+                .entry(connection.output)
+                .or_default()
+                .push((connection.input, connection.weight));
+        }
+        let mut resolved: HashSet<usize> = genome
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.kind, NodeKind::Input | NodeKind::Bias))
+            .map(|node| node.id)
+            .collect();
+        let mut pending: Vec<usize> = genome
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.kind, NodeKind::Hidden | NodeKind::Output))
+            .map(|node| node.id)
+            .collect();
+        let mut order: Vec<(usize, Vec<(usize, f32)>)> = vec![];
+        while !pending.is_empty() {
+            let ready: Vec<usize> = pending
+                .iter()
+                .copied()
+                .filter(|node| {
+                    incoming
+                        .get(node)
+                        .is_none_or(|sources| sources.iter().all(|(source, _)| resolved.contains(source)))
+                })
+                .collect();
+            assert!(!ready.is_empty(), "Genome contains a cycle");
+            for node in ready {
+                order.push((node, incoming.remove(&node).unwrap_or_default()));
+                resolved.insert(node);
+                pending.retain(|&other| other != node);
+            }
+        }
+        Self {
+            inputs: genome.nodes.iter().filter(|node| node.kind == NodeKind::Input).count(),
+            outputs: genome
+                .nodes
+                .iter()
+                .filter(|node| node.kind == NodeKind::Output)
+                .map(|node| node.id)
+                .collect(),
+            order,
+            hidden: genome
+                .nodes
+                .iter()
+                .filter(|node| node.kind == NodeKind::Hidden)
+                .map(|node| node.id)
+                .collect(),
+        }
+    }
+
+    pub fn activate(&self, state: &Array1<f32>) -> Array1<f32> {
+        let mut values: HashMap<usize, f32> = (0..self.inputs).map(|i| (i, state[i])).collect();
+        values.insert(self.inputs, 1.);
+        for (node, sources) in &self.order {
+            let sum: f32 = sources.iter().map(|(source, weight)| values[source] * weight).sum();
+            values.insert(*node, if self.hidden.contains(node) { sum.tanh() } else { sum });
+        }
+        self.outputs.iter().map(|output| values[output]).collect()
+    }
+
+    // greedy action, usable as a run_game chooser
+    pub fn choose(&self, state: &Array1<f32>) -> usize {
+        model::argmax(&self.activate(state))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Species {
+    pub representative: Genome,
+    pub members: Vec<usize>,
+    pub best_fitness: f32,
+    pub stagnant_generations: u16,
+}
+
+pub struct Population {
+    pub genomes: Vec<Genome>,
+    pub species: Vec<Species>,
+    pub tracker: InnovationTracker,
+    pub best: Genome,
+    config: NeatConfig,
+    // of the genomes' weights, their mutations and the fitness episodes
+    seed: u64,
+    rng: rand::rngs::StdRng,
+}
+
+impl Population {
+    pub fn new(inputs: usize, outputs: usize, config: &NeatConfig, seed: u64) -> Self {
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut tracker = InnovationTracker::new(inputs + 1 + outputs);
+        let genomes: Vec<Genome> = (0..config.population)
+            .map(|_| Genome::minimal(inputs, outputs, &mut tracker, &mut rng))
+            .collect();
+        Self {
+            best: genomes[0].clone(),
+            genomes,
+            species: vec![],
+            tracker,
+            config: config.clone(),
+            seed,
+            rng,
+        }
+    }
+
+    fn speciate(&mut self) {
+        for species in &mut self.species {
+            species.members.clear();
+        }
+        let threshold: f32 = self.config.compatibility_threshold;
+        for (i, genome) in self.genomes.iter().enumerate() {
+            match self
+                .species
+                .iter_mut()
+                .find(|species| species.representative.compatibility_distance(genome) < threshold)
+            {
+                Some(species) => species.members.push(i),
+                None => self.species.push(Species {
+                    representative: genome.clone(),
+                    members: vec![i],
+                    best_fitness: f32::NEG_INFINITY,
+                    stagnant_generations: 0,
+                }),
+            }
+        }
+        self.species.retain(|species| !species.members.is_empty());
+        let genomes: &Vec<Genome> = &self.genomes;
+        for species in &mut self.species {
+            species.members.sort_by(|&a, &b| genomes[b].fitness.partial_cmp(&genomes[a].fitness).unwrap());
+            let champion: f32 = genomes[species.members[0]].fitness;
+            if champion > species.best_fitness {
+                species.best_fitness = champion;
+                species.stagnant_generations = 0;
+            } else {
+                species.stagnant_generations += 1;
+            }
+            species.representative = genomes[species.members[0]].clone();
+        }
+        // drop stagnant species, but always keep the historically strongest one
+        let strongest: f32 = self
+            .species
+            .iter()
+            .map(|species| species.best_fitness)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let limit: u16 = self.config.stagnation_limit;
+        self.species
+            .retain(|species| species.stagnant_generations < limit || species.best_fitness >= strongest);
+    }
+
+    // offspring per species, proportional to the summed shared (fitness / size) fitness
+    fn offspring_counts(&self) -> Vec<usize> {
+        let population: usize = self.config.population;
+        let lowest: f32 = self.genomes.iter().map(|genome| genome.fitness).fold(f32::INFINITY, f32::min);
+        let shared: Vec<f32> = self
+            .species
+            .iter()
+            .map(|species| {
+                species
+                    .members
+                    .iter()
+                    .map(|&i| self.genomes[i].fitness - lowest + 1e-3)
+                    .sum::<f32>()
+                    / species.members.len() as f32
+            })
+            .collect();
+        let total: f32 = shared.iter().sum();
+        let mut counts: Vec<usize> = shared
+            .iter()
+            .map(|fitness| (fitness / total * population as f32).floor() as usize)
+            .collect();
+        // hand the rounding remainder to the strongest species
+        let strongest: usize = model::argmax(&Array1::from(shared));
+        counts[strongest] += population - counts.iter().sum::<usize>();
+        counts
+    }
+
+    fn reproduce(&mut self) {
+        let counts: Vec<usize> = self.offspring_counts();
+        let mut next: Vec<Genome> = Vec::with_capacity(self.config.population);
+        for (species, &count) in self.species.iter().zip(counts.iter()) {
+            if count == 0 {
+                continue;
+            }
+            let mut remaining: usize = count;
+            if species.members.len() >= ELITISM_MIN_SPECIES_SIZE {
+                next.push(self.genomes[species.members[0]].clone());
+                remaining -= 1;
+            }
+            let survivors: usize = ((species.members.len() as f32 * self.config.survival_fraction).ceil() as usize).max(1);
+            let parents: &[usize] = &species.members[..survivors];
+            for _ in 0..remaining {
+                let first: &Genome = &self.genomes[*parents.choose(&mut self.rng).unwrap()];
+                let mut child: Genome = if parents.len() > 1 && self.rng.random::<f32>() < self.config.crossover_probability {
+                    let second: &Genome = &self.genomes[*parents.choose(&mut self.rng).unwrap()];
+                    if first.fitness >= second.fitness {
+                        first.crossover(second, &mut self.rng)
+                    } else {
+                        second.crossover(first, &mut self.rng)
+                    }
+                } else {
+                    first.clone()
+                };
+                child.mutate(&mut self.tracker, &self.config, &mut self.rng);
+                next.push(child);
+            }
+        }
+        self.genomes = next;
+    }
+
+    // Scores every genome on the generation's seeded episodes of the
+    // environment, then speciates and breeds.
+    pub fn step(&mut self, generation: u16, environment: &EnvironmentConfig) -> f32 {
+        let seeds: Vec<u64> = evolution::generation_seeds(self.seed, generation, self.config.evaluation_episodes);
+        for genome in &mut self.genomes {
+            let network: FeedForward = FeedForward::from_genome(genome);
+            genome.fitness = evolution::evaluate_with(|state| network.choose(state), environment, &seeds);
+        }
+        let champion: &Genome = self
+            .genomes
+            .iter()
+            .max_by(|a, b| a.fitness.partial_cmp(&b.fitness).unwrap())
+            .unwrap();
+        let champion_fitness: f32 = champion.fitness;
+        if champion_fitness > self.best.fitness {
+            self.best = champion.clone();
+        }
+        self.speciate();
+        println!(
+            "Generation {}\tBest: {}\tSpecies: {}\tHidden nodes: {}",
+            generation,
+            champion_fitness,
+            self.species.len(),
+            self.best.nodes.iter().filter(|node| node.kind == NodeKind::Hidden).count()
+        );
+        self.reproduce();
+        champion_fitness
+    }
+}
+
+// Evolves a population for the configured generations on the environment and
+// returns the best genome found.
+pub fn evolve(inputs: usize, outputs: usize, environment: &EnvironmentConfig, config: &NeatConfig, seed: u64) -> Genome {
+    let mut population = Population::new(inputs, outputs, config, seed);
+    for generation in 0..config.generations {
+        population.step(generation, environment);
+    }
+    population.best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::SEED;
+
+    #[test]
+    fn fitness_is_scored_on_the_configured_environment() {
+        let environment = EnvironmentConfig {
+            max_steps: 3,
+            ..Default::default()
+        };
+        let game = environment.build(0);
+        let config = NeatConfig::default();
+        let mut population = Population::new(game.observation_space, game.action_space, &config, SEED);
+        let champion: f32 = population.step(0, &environment);
+        let network: FeedForward = FeedForward::from_genome(&population.best);
+        let seeds: Vec<u64> = evolution::generation_seeds(SEED, 0, config.evaluation_episodes);
+        let rescored: f32 = evolution::evaluate_with(|state| network.choose(state), &environment, &seeds);
+        assert_eq!(champion, population.best.fitness);
+        assert_eq!(rescored, champion);
+    }
+
+    fn innovations(genome: &Genome) -> Vec<usize> {
+        genome.connections.iter().map(|connection| connection.innovation).collect()
+    }
+
+    // a minimal genome and a copy grown by one node split
+    fn parents() -> (Genome, Genome) {
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut tracker = InnovationTracker::new(3 + 1 + 2);
+        let minimal: Genome = Genome::minimal(3, 2, &mut tracker, &mut rng);
+        let mut grown: Genome = minimal.clone();
+        grown.mutate_add_node(&mut tracker, &mut rng);
+        (minimal, grown)
+    }
+
+    #[test]
+    fn crossover_takes_unmatched_genes_from_the_fitter_parent() {
+        let (minimal, grown) = parents();
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let child: Genome = minimal.crossover(&grown, &mut rng);
+        assert_eq!(innovations(&child), innovations(&minimal));
+        assert_eq!(child.nodes.len(), minimal.nodes.len());
+        let child: Genome = grown.crossover(&minimal, &mut rng);
+        assert_eq!(innovations(&child), innovations(&grown));
+        assert_eq!(child.nodes.len(), grown.nodes.len());
+        // the grown parent's split node must still be reachable
+        assert!(child.connections.iter().any(|connection| connection.enabled && connection.input > 5));
+    }
+
+    #[test]
+    fn compatibility_distance_counts_unmatched_genes() {
+        let (minimal, grown) = parents();
+        assert_eq!(minimal.compatibility_distance(&minimal), 0.);
+        let distance: f32 = minimal.compatibility_distance(&grown);
+        // the split adds two excess genes to the grown genome's eight
+        assert!((distance - EXCESS_COEFFICIENT * 2. / 10.).abs() < 1e-6);
+        assert_eq!(grown.compatibility_distance(&minimal), distance);
+    }
+
+    #[test]
+    fn speciation_follows_the_compatibility_threshold() {
+        let species = |threshold: f32| -> usize {
+            let config = NeatConfig {
+                population: 12,
+                compatibility_threshold: threshold,
+                ..Default::default()
+            };
+            let mut population = Population::new(3, 2, &config, SEED);
+            for (i, genome) in population.genomes.iter_mut().enumerate() {
+                genome.fitness = i as f32;
+            }
+            population.speciate();
+            assert_eq!(population.species.iter().map(|species| species.members.len()).sum::<usize>(), 12);
+            population.species.len()
+        };
+        assert_eq!(species(1e9), 1);
+        // randomly initialised weights always differ a little
+        assert_eq!(species(1e-9), 12);
+    }
+}
@@ -0,0 +1,323 @@
+use std::io;
+use std::path::Path;
+
+use ndarray::Array1;
+use rand::{Rng, SeedableRng};
+use rand_distr::Distribution;
+use rand_distr::weighted::WeightedIndex;
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+use crate::train::{self, Experience};
+use crate::transitions::Record;
+
+// Hyperparameters of train and of both constraints, the offline table of an
+// experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OfflineConfig {
+    pub updates: usize,
+    pub minibatch_size: usize,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    // updates between copies of the online network into the target
+    pub target_update_frequency: usize,
+    // weight of CQL's penalty
+    pub cql_alpha: f32,
+    // BCQ's relative probability cut-off, see Constraint::Bcq
+    pub bcq_threshold: f32,
+}
+
+impl Default for OfflineConfig {
+    fn default() -> Self {
+        Self {
+            updates: 5000,
+            minibatch_size: 32,
+            gamma: 0.99,
+            learning_rate: 0.001,
+            target_update_frequency: 100,
+            cql_alpha: 1.0,
+            bcq_threshold: 0.3,
+        }
+    }
+}
+
+impl OfflineConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.updates == 0 || self.minibatch_size == 0 || self.target_update_frequency == 0 {
+            return Err("updates, minibatch_size and target_update_frequency must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.gamma) {
+            return Err(format!("gamma must be in [0, 1], got {}", self.gamma));
+        }
+        if !(self.learning_rate > 0. && self.learning_rate.is_finite()) {
+            return Err(format!("learning_rate must be positive, got {}", self.learning_rate));
+        }
+        if !(self.cql_alpha >= 0. && self.cql_alpha.is_finite()) {
+            return Err(format!("cql_alpha must not be negative, got {}", self.cql_alpha));
+        }
+        if !(0. ..=1.).contains(&self.bcq_threshold) {
+            return Err(format!("bcq_threshold must be in [0, 1], got {}", self.bcq_threshold));
+        }
+        Ok(())
+    }
+}
+
+// Transitions logged by a previous run, see TrainConfig::dataset_path. Sampled in
+// proportion to their priorities when the file has them, uniformly otherwise.
+pub struct Dataset {
+    transitions: Vec<Experience>,
+    priorities: Option<WeightedIndex<f32>>,
+}
+
+impl Dataset {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let records: Vec<Record> = train::load_transitions(path)?;
+        let priorities: Option<Vec<f32>> = records.iter().map(|record| record.priority).collect();
+        let priorities: Option<WeightedIndex<f32>> = priorities
+            .filter(|priorities| !priorities.is_empty())
+            .map(|priorities| {
+                // negative, non-finite or all zero priorities cannot be sampled from
+                WeightedIndex::new(priorities).map_err(|error| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid transition priorities: {}", error),
+                    )
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            priorities,
+            transitions: records.into_iter().map(|record| record.experience).collect(),
+        })
+    }
+
+    fn sample_index(&self, rng: &mut impl Rng) -> usize {
+        match &self.priorities {
+            Some(priorities) => priorities.sample(rng),
+            None => rng.random_range(0..self.transitions.len()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+}
+
+pub enum Constraint {
+    // CQL: adds alpha * (logsumexp_a Q(s, a) - Q(s, a_data)) to the TD loss,
+    // pushing down the values of actions the data never took
+    Cql { alpha: f32 },
+    // discrete BCQ: only actions the cloned behaviour policy gives at least
+    // threshold times its most likely action's probability can be chosen
+    Bcq { threshold: f32, behavior: model::Model },
+}
+
+pub struct OfflineAgent {
+    pub q_network: model::Model,
+    pub constraint: Constraint,
+}
+
+// actions the behaviour policy considers likely enough in this state
+fn allowed_actions(behavior: &mut model::Model, state: &Array1<f32>, threshold: f32) -> Vec<bool> {
+    let probabilities: Array1<f32> = model::softmax(behavior.forward(state));
+    let most_likely: f32 = probabilities.fold(0., |acc, &p| acc.max(p));
+    probabilities.iter().map(|&p| p >= threshold * most_likely).collect()
+}
+
+// argmax over the allowed actions only
+fn constrained_argmax(values: &Array1<f32>, allowed: &[bool]) -> usize {
+    let mut best: usize = 0;
+    let mut best_value: f32 = f32::NEG_INFINITY;
+    for (action, &value) in values.iter().enumerate() {
+        if allowed[action] && value > best_value {
+            best = action;
+            best_value = value;
+        }
+    }
+    best
+}
+
+impl OfflineAgent {
+    pub fn new(q_network: model::Model, constraint: Constraint) -> Self {
+        Self { q_network, constraint }
+    }
+
+    pub fn greedy_action(&mut self, observation: &Array1<f32>) -> usize {
+        let values: Array1<f32> = self.q_network.forward(observation).clone();
+        match &mut self.constraint {
+            Constraint::Cql { .. } => model::argmax(&values),
+            Constraint::Bcq { threshold, behavior } => {
+                constrained_argmax(&values, &allowed_actions(behavior, observation, *threshold))
+            }
+        }
+    }
+}
+
+// Fits the Q-network to the dataset alone, never stepping a game. Returns the
+// mean absolute TD error of every update.
+pub fn train(agent: &mut OfflineAgent, dataset: &Dataset, config: &OfflineConfig, seed: u64) -> Vec<f32> {
+    assert!(!dataset.is_empty(), "Offline training needs at least one transition");
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut target: model::Model = agent.q_network.clone();
+    let mut history: Vec<f32> = vec![];
+    for update in 1..=config.updates {
+        let mut td_error: f32 = 0.;
+        // CQL's penalty or BCQ's behaviour cloning loss
+        let mut constraint_loss: f32 = 0.;
+        for _ in 0..config.minibatch_size {
+            let experience: &Experience = &dataset.transitions[dataset.sample_index(&mut rng)];
+            let next_value: f32 = if experience.done {
+                0.
+            } else {
+                match &mut agent.constraint {
+                    Constraint::Cql { .. } => target
+                        .forward(&experience.next_state)
+                        .fold(f32::NEG_INFINITY, |acc, &q| acc.max(q)),
+                    Constraint::Bcq { threshold, behavior } => {
+                        // the online network picks among allowed actions, the target evaluates it
+                        let allowed: Vec<bool> = allowed_actions(behavior, &experience.next_state, *threshold);
+                        let next_action: usize =
+                            constrained_argmax(&agent.q_network.forward(&experience.next_state).clone(), &allowed);
+                        target.forward(&experience.next_state)[next_action]
+                    }
+                }
+            };
+            let values: Array1<f32> = agent.q_network.forward(&experience.state).clone();
+            let error: f32 = values[experience.action] - (experience.reward + config.gamma * next_value);
+            td_error += error.abs();
+            let mut loss_derivative: Array1<f32> = Array1::zeros(values.len());
+            loss_derivative[experience.action] = error;
+            match &mut agent.constraint {
+                Constraint::Cql { alpha } => {
+                    // d/dQ logsumexp = softmax
+                    let probabilities: Array1<f32> = model::softmax(&values);
+                    let max: f32 = values.fold(f32::NEG_INFINITY, |acc, &q| acc.max(q));
+                    let logsumexp: f32 = max + values.mapv(|q| (q - max).exp()).sum().ln();
+                    constraint_loss += logsumexp - values[experience.action];
+                    loss_derivative.scaled_add(*alpha, &probabilities);
+                    loss_derivative[experience.action] -= *alpha;
+                }
+                Constraint::Bcq { behavior, .. } => {
+                    let mut derivative: Array1<f32> = model::softmax(behavior.forward(&experience.state));
+                    constraint_loss -= derivative[experience.action].max(f32::EPSILON).ln();
+                    derivative[experience.action] -= 1.;
+                    behavior.backprop(&experience.state, &derivative);
+                }
+            }
+            agent.q_network.backprop(&experience.state, &loss_derivative);
+        }
+        agent.q_network.apply_gradients(config.learning_rate / config.minibatch_size as f32);
+        if let Constraint::Bcq { behavior, .. } = &mut agent.constraint {
+            behavior.apply_gradients(config.learning_rate / config.minibatch_size as f32);
+        }
+        if update.is_multiple_of(config.target_update_frequency) {
+            target = agent.q_network.clone();
+        }
+        td_error /= config.minibatch_size as f32;
+        if update.is_multiple_of((config.updates / 10).max(1)) {
+            println!(
+                "Update {}\tTD error: {}\tConstraint loss: {}",
+                update,
+                td_error,
+                constraint_loss / config.minibatch_size as f32
+            );
+        }
+        history.push(td_error);
+    }
+    agent.q_network.set_training(false);
+    history
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::train::SEED;
+    use crate::transitions::TransitionWriter;
+
+    #[test]
+    fn invalid_priorities_are_an_error() {
+        let path: PathBuf = std::env::temp_dir().join(format!("offline_priorities_{}.bin", std::process::id()));
+        let mut writer: TransitionWriter = TransitionWriter::create(&path, 1, true).unwrap();
+        let experience = Experience {
+            state: Array1::zeros(1),
+            action: 0,
+            reward: 1.,
+            next_state: Array1::zeros(1),
+            done: true,
+        };
+        writer.write(&experience, false, Some(-1.)).unwrap();
+        writer.finish().unwrap();
+        let error: io::Error = Dataset::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn constrained_argmax_skips_disallowed_actions() {
+        let values: Array1<f32> = Array1::from(vec![5., 1., 3.]);
+        assert_eq!(constrained_argmax(&values, &[false, true, true]), 2);
+        assert_eq!(constrained_argmax(&values, &[true, true, true]), 0);
+    }
+
+    // A one-action dataset of terminal zero-reward steps, and a Q-network that
+    // starts out valuing both actions at 2 there.
+    fn one_action_setup(constraint: impl FnOnce(model::Model) -> Constraint) -> (OfflineAgent, Dataset) {
+        let network = || {
+            let mut network = model::Model::new();
+            network.add_layer(1, 2, false);
+            network.set_flat_parameters(&Array1::ones(network.num_parameters()));
+            network
+        };
+        let experience = Experience {
+            state: Array1::ones(1),
+            action: 0,
+            reward: 0.,
+            next_state: Array1::ones(1),
+            done: true,
+        };
+        let dataset = Dataset {
+            transitions: vec![experience; 8],
+            priorities: None,
+        };
+        (OfflineAgent::new(network(), constraint(network())), dataset)
+    }
+
+    fn config() -> OfflineConfig {
+        OfflineConfig {
+            updates: 300,
+            minibatch_size: 4,
+            learning_rate: 0.1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cql_penalises_actions_missing_from_the_data() {
+        let state: Array1<f32> = Array1::ones(1);
+        for (alpha, expected) in [(0., 1), (1., 0)] {
+            let (mut agent, dataset) = one_action_setup(|_| Constraint::Cql { alpha });
+            let history: Vec<f32> = train(&mut agent, &dataset, &config(), SEED);
+            assert_eq!(history.len(), 300);
+            // without the penalty the untrained action keeps its initial value
+            assert_eq!(agent.greedy_action(&state), expected, "alpha {}", alpha);
+        }
+    }
+
+    #[test]
+    fn bcq_only_picks_actions_the_behaviour_policy_takes() {
+        let state: Array1<f32> = Array1::ones(1);
+        let (mut agent, dataset) = one_action_setup(|behavior| Constraint::Bcq { threshold: 0.3, behavior });
+        train(&mut agent, &dataset, &config(), SEED);
+        let values: Array1<f32> = agent.q_network.forward(&state).clone();
+        assert!(values[1] > values[0]);
+        assert_eq!(agent.greedy_action(&state), 0);
+    }
+}
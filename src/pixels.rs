@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use crate::game::Snapshot;
+use crate::model::ImageShape;
+use crate::rasterizer::{Frame, Rasterizer};
+use crate::renderer::Renderer;
+
+// Image observations in the style of Atari DQN: every frame is rendered at
+// downsample times the observed resolution, converted to grayscale and
+// averaged down, and the last `frames` of them are stacked.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PixelConfig {
+    pub width: usize,
+    pub height: usize,
+    // rendered pixels per observed pixel along each axis
+    pub downsample: usize,
+    pub frames: usize,
+}
+
+impl Default for PixelConfig {
+    fn default() -> Self {
+        Self {
+            width: 84,
+            height: 84,
+            downsample: 2,
+            frames: 4,
+        }
+    }
+}
+
+impl PixelConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 || self.downsample == 0 || self.frames == 0 {
+            return Err("pixel width, height, downsample and frames must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    // length of the flattened observation
+    pub fn observation_size(&self) -> usize {
+        self.shape().len()
+    }
+
+    // the observation as an image with one channel per frame
+    pub fn shape(&self) -> ImageShape {
+        ImageShape::new(self.frames, self.height, self.width)
+    }
+}
+
+// luminance of an RGB pixel, in [0, 1]
+fn grayscale(pixel: &[u8]) -> f32 {
+    (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32) / 255.
+}
+
+#[derive(Clone)]
+pub struct FrameStack {
+    pub config: PixelConfig,
+    rasterizer: Rasterizer,
+    // oldest first
+    frames: VecDeque<Array1<f32>>,
+}
+
+impl FrameStack {
+    pub fn new(config: PixelConfig) -> Self {
+        config.validate().unwrap();
+        Self {
+            rasterizer: Rasterizer::new(config.width * config.downsample, config.height * config.downsample),
+            frames: VecDeque::with_capacity(config.frames),
+            config,
+        }
+    }
+
+    fn observe_frame(&mut self, snapshot: &Snapshot) -> Array1<f32> {
+        self.rasterizer.draw(snapshot);
+        let frame: &Frame = self.rasterizer.frame();
+        let block: usize = self.config.downsample;
+        let mut pixels: Array1<f32> = Array1::zeros(self.config.height * self.config.width);
+        for y in 0..frame.height {
+            let row: &[u8] = &frame.pixels[3 * y * frame.width..3 * (y + 1) * frame.width];
+            for (x, pixel) in row.chunks_exact(3).enumerate() {
+                pixels[(y / block) * self.config.width + x / block] += grayscale(pixel);
+            }
+        }
+        pixels / (block * block) as f32
+    }
+
+    // Starts an episode: the stack holds copies of the first frame until
+    // enough steps have been taken to fill it.
+    pub fn restart(&mut self, snapshot: &Snapshot) {
+        let frame: Array1<f32> = self.observe_frame(snapshot);
+        self.frames.clear();
+        self.frames.resize(self.config.frames, frame);
+    }
+
+    pub fn push(&mut self, snapshot: &Snapshot) {
+        let frame: Array1<f32> = self.observe_frame(snapshot);
+        self.frames.pop_front();
+        self.frames.push_back(frame);
+    }
+
+    // Frames oldest first, each row by row from the top, i.e. a flattened
+    // [frames, height, width] image.
+    pub fn to_vec(&self, output: &mut Array1<f32>) {
+        let size: usize = self.config.height * self.config.width;
+        for (i, frame) in self.frames.iter().enumerate() {
+            output.slice_mut(ndarray::s![i * size..(i + 1) * size]).assign(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::train::SEED;
+
+    fn config() -> PixelConfig {
+        PixelConfig {
+            width: 12,
+            height: 10,
+            downsample: 2,
+            frames: 3,
+        }
+    }
+
+    fn observe(stack: &FrameStack) -> Vec<Array1<f32>> {
+        let mut output: Array1<f32> = Array1::from_elem(stack.config.observation_size(), -1.);
+        stack.to_vec(&mut output);
+        let size: usize = stack.config.height * stack.config.width;
+        output.exact_chunks(size).into_iter().map(|frame| frame.to_owned()).collect()
+    }
+
+    #[test]
+    fn restart_fills_the_stack_with_the_first_frame() {
+        let game = Game::with_seed(SEED);
+        let mut stack = FrameStack::new(config());
+        stack.restart(&game.snapshot());
+        let frames: Vec<Array1<f32>> = observe(&stack);
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| *frame == frames[0]));
+        assert!(frames[0].iter().all(|&value| (0. ..=1.).contains(&value)));
+        // the bottom row is all ground, the top one empty sky
+        assert!(frames[0].slice(ndarray::s![9 * 12..]).iter().all(|&value| value > 0.99));
+        assert!(frames[0].slice(ndarray::s![..12]).iter().all(|&value| value == 0.));
+    }
+
+    #[test]
+    fn pushing_shifts_out_the_oldest_frame() {
+        let mut game = Game::with_seed(SEED);
+        let mut stack = FrameStack::new(config());
+        stack.restart(&game.snapshot());
+        let first: Array1<f32> = observe(&stack)[0].clone();
+        game.step(1);
+        stack.push(&game.snapshot());
+        let frames: Vec<Array1<f32>> = observe(&stack);
+        assert_eq!(frames[0], first);
+        assert_eq!(frames[1], first);
+        assert_ne!(frames[2], first);
+        let second: Array1<f32> = frames[2].clone();
+        for _ in 0..2 {
+            game.step(0);
+            stack.push(&game.snapshot());
+        }
+        let frames: Vec<Array1<f32>> = observe(&stack);
+        assert_eq!(frames[0], second);
+    }
+}
@@ -0,0 +1,400 @@
+use ndarray::Array1;
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_distr::{Distribution, weighted::WeightedIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::{game, model};
+
+// Hyperparameters of train for discrete and continuous actions, the ppo
+// table of an experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PpoConfig {
+    pub updates: u16,
+    // steps collected per update
+    pub horizon: usize,
+    pub epochs: usize,
+    pub minibatch_size: usize,
+    pub gamma: f32,
+    pub gae_lambda: f32,
+    pub clip_range: f32,
+    pub value_clip_range: f32,
+    pub actor_learning_rate: f32,
+    pub critic_learning_rate: f32,
+    pub value_loss_weight: f32,
+    // weight of the entropy bonus that keeps the policy exploring
+    pub entropy_weight: f32,
+    pub target_kl: f32,
+    // of the Gaussian policy's actions, see Policy::gaussian
+    pub initial_log_std: f32,
+}
+
+impl Default for PpoConfig {
+    fn default() -> Self {
+        Self {
+            updates: 100,
+            horizon: 128,
+            epochs: 4,
+            minibatch_size: 32,
+            gamma: 0.99,
+            gae_lambda: 0.95,
+            clip_range: 0.2,
+            value_clip_range: 0.2,
+            actor_learning_rate: 0.0003,
+            critic_learning_rate: 0.001,
+            value_loss_weight: 0.5,
+            entropy_weight: 0.01,
+            target_kl: 0.02,
+            initial_log_std: -0.5,
+        }
+    }
+}
+
+impl PpoConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.updates == 0 || self.horizon == 0 || self.epochs == 0 || self.minibatch_size == 0 {
+            return Err("updates, horizon, epochs and minibatch_size must be positive".to_string());
+        }
+        if !((0. ..=1.).contains(&self.gamma) && (0. ..=1.).contains(&self.gae_lambda)) {
+            return Err("gamma and gae_lambda must be in [0, 1]".to_string());
+        }
+        if !(self.clip_range > 0. && self.value_clip_range > 0. && self.target_kl > 0.) {
+            return Err("clip_range, value_clip_range and target_kl must be positive".to_string());
+        }
+        if !(self.actor_learning_rate > 0. && self.critic_learning_rate > 0.) {
+            return Err("actor_learning_rate and critic_learning_rate must be positive".to_string());
+        }
+        if !(self.value_loss_weight >= 0. && self.entropy_weight >= 0.) {
+            return Err("value_loss_weight and entropy_weight must not be negative".to_string());
+        }
+        if !self.initial_log_std.is_finite() {
+            return Err(format!("initial_log_std must be finite, got {}", self.initial_log_std));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Action {
+    Discrete(usize),
+    Continuous(Array1<f32>),
+}
+
+// Categorical policies output one logit per action; Gaussian policies use
+// model::GaussianPolicy.
+#[derive(Clone)]
+pub enum Policy {
+    Categorical(model::Model),
+    Gaussian(model::GaussianPolicy),
+}
+
+impl Policy {
+    // unsquashed Gaussian with a state-independent log-std; the lander clamps the throttle
+    pub fn gaussian(mean: model::Model, initial_log_std: f32) -> Self {
+        let dims: usize = mean.output_size();
+        Policy::Gaussian(model::GaussianPolicy::new(
+            mean,
+            dims,
+            model::LogStd::parameter(dims, initial_log_std),
+            false,
+        ))
+    }
+
+    // samples an action, returning it with its log-probability
+    pub fn act(&mut self, state: &Array1<f32>, rng: &mut rand::rngs::StdRng) -> (Action, f32) {
+        match self {
+            Policy::Categorical(logits) => {
+                let probabilities: Array1<f32> = model::softmax(logits.forward(state));
+                let choice: usize = WeightedIndex::new(probabilities.iter()).unwrap().sample(rng);
+                (
+                    Action::Discrete(choice),
+                    probabilities[choice].max(f32::EPSILON).ln(),
+                )
+            }
+            Policy::Gaussian(gaussian) => {
+                let sample: model::GaussianSample = gaussian.sample(state, rng);
+                (Action::Continuous(sample.action), sample.log_prob)
+            }
+        }
+    }
+
+    pub fn deterministic_action(&mut self, state: &Array1<f32>) -> Action {
+        match self {
+            Policy::Categorical(logits) => Action::Discrete(model::argmax(logits.forward(state))),
+            Policy::Gaussian(gaussian) => Action::Continuous(gaussian.deterministic_action(state)),
+        }
+    }
+
+    // Forwards the state, so it also prepares the network for backprop().
+    pub fn log_prob_and_entropy(&mut self, state: &Array1<f32>, action: &Action) -> (f32, f32) {
+        match (self, action) {
+            (Policy::Categorical(logits), Action::Discrete(choice)) => {
+                let probabilities: Array1<f32> = model::softmax(logits.forward(state));
+                (
+                    probabilities[*choice].max(f32::EPSILON).ln(),
+                    model::entropy(&probabilities),
+                )
+            }
+            (Policy::Gaussian(gaussian), Action::Continuous(value)) => {
+                let log_prob: f32 = gaussian.log_prob(state, value);
+                (log_prob, gaussian.entropy())
+            }
+            _ => panic!("Action type does not match the policy"),
+        }
+    }
+
+    // Accumulates the gradient of a loss given dL/dlog_prob and dL/dentropy.
+    // Must follow a log_prob_and_entropy() call for the same state.
+    pub fn backprop(
+        &mut self,
+        state: &Array1<f32>,
+        action: &Action,
+        log_prob_derivative: f32,
+        entropy_derivative: f32,
+    ) {
+        match (self, action) {
+            (Policy::Categorical(logits), Action::Discrete(choice)) => {
+                let probabilities: Array1<f32> = model::softmax(logits.output());
+                let entropy: f32 = model::entropy(&probabilities);
+                // dlogp/dz = onehot - p, dH/dz = -p * (log p + H)
+                let mut derivative: Array1<f32> = -probabilities.clone() * log_prob_derivative;
+                derivative[*choice] += log_prob_derivative;
+                derivative -= &(probabilities.mapv(|p| p * (p.max(f32::EPSILON).ln() + entropy))
+                    * entropy_derivative);
+                logits.backprop(state, &derivative);
+            }
+            (Policy::Gaussian(gaussian), Action::Continuous(value)) => {
+                gaussian.backprop_log_prob(state, value, log_prob_derivative, entropy_derivative);
+            }
+            _ => panic!("Action type does not match the policy"),
+        }
+    }
+
+    pub fn apply_gradients(&mut self, learning_rate: f32) {
+        match self {
+            Policy::Categorical(logits) => logits.apply_gradients(learning_rate),
+            Policy::Gaussian(gaussian) => gaussian.apply_gradients(learning_rate),
+        }
+    }
+}
+
+pub fn step(game: &mut game::Game, action: &Action) -> (f32, bool) {
+    match action {
+        Action::Discrete(choice) => game.step(*choice),
+        Action::Continuous(value) => game.step_continuous(value),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpdateStats {
+    pub policy_loss: f32,
+    pub value_loss: f32,
+    pub entropy: f32,
+    pub approx_kl: f32,
+    pub clip_fraction: f32,
+    pub epochs: usize,
+}
+
+struct Sample {
+    state: Array1<f32>,
+    action: Action,
+    log_prob: f32,
+    value: f32,
+    advantage: f32,
+    value_target: f32,
+}
+
+// Generalized Advantage Estimation over one rollout. `values` holds one more
+// entry than `rewards`: the value of the state after the last step.
+pub fn gae(rewards: &[f32], values: &[f32], dones: &[bool], gamma: f32, lambda: f32) -> Vec<f32> {
+    let mut advantages: Vec<f32> = vec![0.; rewards.len()];
+    let mut running: f32 = 0.;
+    for t in (0..rewards.len()).rev() {
+        let not_done: f32 = if dones[t] { 0. } else { 1. };
+        let delta: f32 = rewards[t] + gamma * values[t + 1] * not_done - values[t];
+        running = delta + gamma * lambda * not_done * running;
+        advantages[t] = running;
+    }
+    advantages
+}
+
+fn collect_rollout(
+    game: &mut game::Game,
+    policy: &mut Policy,
+    critic: &mut model::Model,
+    config: &PpoConfig,
+    rng: &mut rand::rngs::StdRng,
+    score: &mut f32,
+) -> Vec<Sample> {
+    let mut state: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut samples: Vec<Sample> = vec![];
+    let mut rewards: Vec<f32> = vec![];
+    let mut values: Vec<f32> = vec![];
+    let mut dones: Vec<bool> = vec![];
+    for _ in 0..config.horizon {
+        game.observe(&mut state);
+        let (action, log_prob) = policy.act(&state, rng);
+        let value: f32 = critic.forward(&state)[0];
+        let (reward, finished) = step(game, &action);
+        *score += reward;
+        if finished {
+            println!("Scored: {}", score);
+            *score = 0.;
+            game.reset();
+        }
+        samples.push(Sample {
+            state: state.clone(),
+            action,
+            log_prob,
+            value,
+            advantage: 0.,
+            value_target: 0.,
+        });
+        rewards.push(reward);
+        values.push(value);
+        dones.push(finished);
+    }
+    game.observe(&mut state);
+    values.push(critic.forward(&state)[0]);
+    let advantages: Vec<f32> = gae(&rewards, &values, &dones, config.gamma, config.gae_lambda);
+    let mean: f32 = advantages.iter().sum::<f32>() / advantages.len() as f32;
+    let std: f32 = (advantages.iter().map(|a| (a - mean).powi(2)).sum::<f32>() / advantages.len() as f32).sqrt();
+    for (sample, advantage) in samples.iter_mut().zip(advantages) {
+        sample.value_target = advantage + sample.value;
+        sample.advantage = (advantage - mean) / (std + 1e-8);
+    }
+    samples
+}
+
+// Proximal Policy Optimization with the clipped surrogate objective, clipped
+// value loss and an entropy bonus. Epochs over a rollout stop early once the
+// approximate KL to the rollout policy exceeds 1.5 * target_kl.
+pub fn train(
+    game: &mut game::Game,
+    policy: &mut Policy,
+    critic: &mut model::Model,
+    config: &PpoConfig,
+    seed: u64,
+) -> Vec<UpdateStats> {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut score: f32 = 0.;
+    let mut history: Vec<UpdateStats> = vec![];
+    for update in 0..config.updates {
+        let samples: Vec<Sample> = collect_rollout(game, policy, critic, config, &mut rng, &mut score);
+        let mut indices: Vec<usize> = (0..samples.len()).collect();
+        let mut stats = UpdateStats::default();
+        let mut measured: usize = 0;
+        'epochs: for epoch in 0..config.epochs {
+            indices.shuffle(&mut rng);
+            let mut epoch_kl: f32 = 0.;
+            for minibatch in indices.chunks(config.minibatch_size) {
+                for &i in minibatch {
+                    let sample: &Sample = &samples[i];
+                    let (log_prob, entropy) = policy.log_prob_and_entropy(&sample.state, &sample.action);
+                    let ratio: f32 = (log_prob - sample.log_prob).exp();
+                    let clipped_ratio: f32 = ratio.clamp(1. - config.clip_range, 1. + config.clip_range);
+                    let unclipped_objective: f32 = ratio * sample.advantage;
+                    let clipped_objective: f32 = clipped_ratio * sample.advantage;
+                    // the gradient only flows through the ratio when min() picks the unclipped term
+                    let log_prob_derivative: f32 = if unclipped_objective <= clipped_objective {
+                        -ratio * sample.advantage
+                    } else {
+                        0.
+                    };
+                    policy.backprop(&sample.state, &sample.action, log_prob_derivative, -config.entropy_weight);
+
+                    let value: f32 = critic.forward(&sample.state)[0];
+                    let clipped_value: f32 = sample.value
+                        + (value - sample.value).clamp(-config.value_clip_range, config.value_clip_range);
+                    let unclipped_error: f32 = value - sample.value_target;
+                    let clipped_error: f32 = clipped_value - sample.value_target;
+                    let value_derivative: f32 = if unclipped_error.powi(2) >= clipped_error.powi(2) {
+                        unclipped_error
+                    } else {
+                        0.
+                    };
+                    critic.backprop(&sample.state, &Array1::from_elem(1, config.value_loss_weight * value_derivative));
+
+                    stats.policy_loss -= unclipped_objective.min(clipped_objective);
+                    stats.value_loss += 0.5 * unclipped_error.powi(2).max(clipped_error.powi(2));
+                    stats.entropy += entropy;
+                    stats.clip_fraction += if (ratio - 1.).abs() > config.clip_range { 1. } else { 0. };
+                    epoch_kl += sample.log_prob - log_prob;
+                    measured += 1;
+                }
+                policy.apply_gradients(config.actor_learning_rate / minibatch.len() as f32);
+                critic.apply_gradients(config.critic_learning_rate / minibatch.len() as f32);
+            }
+            stats.epochs = epoch + 1;
+            stats.approx_kl = epoch_kl / samples.len() as f32;
+            if stats.approx_kl > 1.5 * config.target_kl {
+                break 'epochs;
+            }
+        }
+        stats.policy_loss /= measured as f32;
+        stats.value_loss /= measured as f32;
+        stats.entropy /= measured as f32;
+        stats.clip_fraction /= measured as f32;
+        println!(
+            "Update {}\tPolicy loss: {}\tValue loss: {}\tEntropy: {}\tKL: {}\tEpochs: {}",
+            update, stats.policy_loss, stats.value_loss, stats.entropy, stats.approx_kl, stats.epochs
+        );
+        history.push(stats);
+    }
+    history
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::SEED;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn gae_spans_td_errors_to_monte_carlo_advantages() {
+        let rewards: [f32; 2] = [1., 2.];
+        let values: [f32; 3] = [0.5, 0.25, 1.];
+        let dones: [bool; 2] = [false, false];
+        // lambda 0: one-step TD errors r + gamma * V(s') - V(s)
+        assert_close(&gae(&rewards, &values, &dones, 0.9, 0.), &[0.725, 2.65]);
+        // lambda 1: discounted return bootstrapped from the last value, minus V(s)
+        assert_close(&gae(&rewards, &values, &dones, 0.9, 1.), &[3.11, 2.65]);
+    }
+
+    #[test]
+    fn gae_does_not_bootstrap_across_episode_ends() {
+        let advantages: Vec<f32> = gae(&[1., 2.], &[0.5, 0.25, 1.], &[true, false], 0.9, 0.95);
+        assert_close(&advantages[..1], &[0.5]);
+    }
+
+    #[test]
+    fn training_reports_every_update() {
+        let mut game = game::Game::with_seed(SEED);
+        game.max_steps = 5;
+        let mut logits = model::Model::with_seed(SEED);
+        logits.add_layer(game.observation_space, game.action_space, false);
+        let mut policy = Policy::Categorical(logits);
+        let mut critic = model::Model::with_seed(SEED + 1);
+        critic.add_layer(game.observation_space, 1, false);
+        let config = PpoConfig {
+            updates: 2,
+            horizon: 8,
+            minibatch_size: 4,
+            ..Default::default()
+        };
+        let history: Vec<UpdateStats> = train(&mut game, &mut policy, &mut critic, &config, SEED);
+        assert_eq!(history.len(), 2);
+        for stats in history {
+            assert!((1..=config.epochs).contains(&stats.epochs));
+            assert!((0. ..=1.).contains(&stats.clip_fraction));
+            assert!(stats.value_loss.is_finite() && stats.entropy > 0.);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use uom::si::f32::Length;
+
+use crate::game::{Pos, Quad, Snapshot};
+use crate::graphics::{ENV_BOX_HEIGHT, ENV_BOX_WIDTH};
+use crate::renderer::{JetParticles, PARTICLE_RADIUS, Renderer};
+
+// macroquad's palette, so offscreen frames look like the window
+const BLACK: [u8; 3] = [0, 0, 0];
+const WHITE: [u8; 3] = [255, 255, 255];
+const PURPLE: [u8; 3] = [199, 122, 255];
+const GRAY: [u8; 3] = [130, 130, 130];
+const BLUE: [u8; 3] = [0, 120, 242];
+// pixels, as adjusted_draw_line's thickness is at 800x800
+const LINE_THICKNESS: f32 = 2.;
+
+// Row-major RGB image, top row first, three bytes per pixel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; 3 * width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i: usize = 3 * (y * self.width + x);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    fn fill(&mut self, color: [u8; 3]) {
+        for pixel in self.pixels.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&color);
+        }
+    }
+
+    // alpha 1 overwrites, anything less blends with what is already there
+    fn blend(&mut self, x: usize, y: usize, color: [u8; 3], alpha: f32) {
+        let i: usize = 3 * (y * self.width + x);
+        for (old, &new) in self.pixels[i..i + 3].iter_mut().zip(&color) {
+            *old = (*old as f32 + alpha * (new as f32 - *old as f32)).round() as u8;
+        }
+    }
+}
+
+// Draws the same scene as the macroquad window into a Frame, on the CPU and
+// without a window. World coordinates map to pixels the way graphics'
+// adjusted_draw_* functions map them: the environment box fills the frame
+// and y points down. A pixel belongs to a shape when its center does.
+#[derive(Clone)]
+pub struct Rasterizer {
+    frame: Frame,
+    jet_particles: JetParticles,
+}
+
+impl Rasterizer {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && height > 0, "Frames need at least one pixel");
+        Self {
+            frame: Frame::new(width, height),
+            jet_particles: JetParticles::new(),
+        }
+    }
+
+    // the last drawn frame
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    // pixels per meter along x and y
+    fn scale(&self) -> (f32, f32) {
+        (
+            self.frame.width as f32 / ENV_BOX_WIDTH.value,
+            self.frame.height as f32 / ENV_BOX_HEIGHT.value,
+        )
+    }
+
+    fn to_screen(&self, x: Length, y: Length) -> (f32, f32) {
+        let (scale_x, scale_y) = self.scale();
+        (scale_x * x.value, scale_y * (*ENV_BOX_HEIGHT - y).value)
+    }
+
+    // Calls shade with the center of every pixel in the box, clipped to the frame.
+    fn for_pixels(&mut self, min: (f32, f32), max: (f32, f32), mut shade: impl FnMut(&mut Frame, usize, usize, f32, f32)) {
+        let clip = |value: f32, size: usize| (value.max(0.) as usize).min(size);
+        let (x0, x1) = (clip(min.0.floor(), self.frame.width), clip(max.0.ceil(), self.frame.width));
+        let (y0, y1) = (clip(min.1.floor(), self.frame.height), clip(max.1.ceil(), self.frame.height));
+        for y in y0..y1 {
+            for x in x0..x1 {
+                shade(&mut self.frame, x, y, x as f32 + 0.5, y as f32 + 0.5);
+            }
+        }
+    }
+
+    fn draw_quad(&mut self, quad: &Quad, color: [u8; 3]) {
+        let (scale_x, scale_y) = self.scale();
+        let (center_x, center_y) = self.to_screen(quad.center.x, quad.center.y);
+        let (sin, cos) = quad.tilt.value.sin_cos();
+        let (half_width, half_height) = (quad.width.value / 2., quad.height.value / 2.);
+        // half the extent of the rotated rectangle along each axis, in pixels
+        let reach_x: f32 = scale_x * (half_width * cos.abs() + half_height * sin.abs());
+        let reach_y: f32 = scale_y * (half_width * sin.abs() + half_height * cos.abs());
+        self.for_pixels(
+            (center_x - reach_x, center_y - reach_y),
+            (center_x + reach_x, center_y + reach_y),
+            |frame, x, y, px, py| {
+                // back to world units relative to the center, y up, then undo the tilt
+                let dx: f32 = (px - center_x) / scale_x;
+                let dy: f32 = (center_y - py) / scale_y;
+                let along: f32 = dx * cos + dy * sin;
+                let across: f32 = -dx * sin + dy * cos;
+                if along.abs() <= half_width && across.abs() <= half_height {
+                    frame.blend(x, y, color, 1.);
+                }
+            },
+        );
+    }
+
+    fn draw_line(&mut self, start: &Pos, end: &Pos, color: [u8; 3]) {
+        let (x1, y1) = self.to_screen(start.x, start.y);
+        let (x2, y2) = self.to_screen(end.x, end.y);
+        let half: f32 = LINE_THICKNESS / 2.;
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let length_squared: f32 = dx * dx + dy * dy;
+        self.for_pixels(
+            (x1.min(x2) - half, y1.min(y2) - half),
+            (x1.max(x2) + half, y1.max(y2) + half),
+            |frame, x, y, px, py| {
+                // distance to the closest point of the segment
+                let t: f32 = if length_squared > 0. {
+                    (((px - x1) * dx + (py - y1) * dy) / length_squared).clamp(0., 1.)
+                } else {
+                    0.
+                };
+                let (ex, ey) = (px - x1 - t * dx, py - y1 - t * dy);
+                if ex * ex + ey * ey <= half * half {
+                    frame.blend(x, y, color, 1.);
+                }
+            },
+        );
+    }
+
+    fn draw_circle(&mut self, center: &Pos, radius: Length, color: [u8; 3], alpha: f32) {
+        let (scale_x, scale_y) = self.scale();
+        let (cx, cy) = self.to_screen(center.x, center.y);
+        let (rx, ry) = (scale_x * radius.value, scale_y * radius.value);
+        self.for_pixels((cx - rx, cy - ry), (cx + rx, cy + ry), |frame, x, y, px, py| {
+            let (ex, ey) = ((px - cx) / rx, (py - cy) / ry);
+            if ex * ex + ey * ey <= 1. {
+                frame.blend(x, y, color, alpha);
+            }
+        });
+    }
+}
+
+impl Renderer for Rasterizer {
+    fn draw(&mut self, snapshot: &Snapshot) {
+        self.jet_particles.advance(snapshot);
+        self.frame.fill(BLACK);
+        let (_, ground_top) = self.to_screen(Length::default(), snapshot.ground);
+        let (width, height) = (self.frame.width as f32, self.frame.height as f32);
+        self.for_pixels((0., ground_top), (width, height), |frame, x, y, _, py| {
+            if py >= ground_top {
+                frame.blend(x, y, WHITE, 1.);
+            }
+        });
+        self.draw_quad(&snapshot.body, PURPLE);
+        for engine in &snapshot.engines {
+            self.draw_quad(engine, GRAY);
+        }
+        for (start, end) in &snapshot.legs {
+            self.draw_line(start, end, BLUE);
+        }
+        let particles: Vec<(Pos, f32)> = self.jet_particles.visible().collect();
+        for (pos, alpha) in particles {
+            self.draw_circle(&pos, *PARTICLE_RADIUS, WHITE, alpha);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::angle::degree;
+    use uom::si::f32::Angle;
+    use uom::si::length::meter;
+
+    use super::*;
+    use crate::game::Game;
+    use crate::train::SEED;
+
+    #[test]
+    fn blending_mixes_with_the_existing_color() {
+        let mut frame = Frame::new(2, 1);
+        frame.blend(1, 0, [200, 100, 50], 1.);
+        assert_eq!(frame.pixel(1, 0), [200, 100, 50]);
+        frame.blend(1, 0, [0, 0, 0], 0.5);
+        assert_eq!(frame.pixel(1, 0), [100, 50, 25]);
+        assert_eq!(frame.pixel(0, 0), BLACK);
+    }
+
+    // a 2 x 1 m rectangle in the middle of a 12 x 12 pixel frame, 2 pixels per meter
+    fn quad_pixels(tilt: f32) -> Frame {
+        let meters = Length::new::<meter>;
+        let mut rasterizer = Rasterizer::new(12, 12);
+        let quad = Quad {
+            center: Pos {
+                x: meters(3.),
+                y: meters(3.),
+            },
+            width: meters(2.),
+            height: meters(1.),
+            tilt: Angle::new::<degree>(tilt),
+        };
+        rasterizer.draw_quad(&quad, WHITE);
+        rasterizer.frame
+    }
+
+    #[test]
+    fn quads_cover_the_pixels_whose_centers_they_contain() {
+        let flat: Frame = quad_pixels(0.);
+        let covered = |frame: &Frame| frame.pixels.chunks_exact(3).filter(|pixel| *pixel == WHITE).count();
+        // 4 pixels wide and 2 high
+        assert_eq!(covered(&flat), 8);
+        assert_eq!(flat.pixel(5, 5), WHITE);
+        assert_eq!(flat.pixel(6, 4), BLACK);
+        // a quarter turn stands it on its end
+        let upright: Frame = quad_pixels(90.);
+        assert_eq!(covered(&upright), 8);
+        assert_eq!(upright.pixel(6, 4), WHITE);
+        assert_eq!(upright.pixel(3, 5), BLACK);
+    }
+
+    #[test]
+    fn snapshots_draw_the_ground_and_the_lander() {
+        let game = Game::with_seed(SEED);
+        let snapshot: Snapshot = game.snapshot();
+        let mut rasterizer = Rasterizer::new(64, 64);
+        rasterizer.draw(&snapshot);
+        let frame: Frame = rasterizer.frame().clone();
+        assert_eq!(frame.pixel(0, 63), WHITE);
+        assert_eq!(frame.pixel(0, 0), BLACK);
+        // the lander starts on the right edge of the box, so look inside its left half
+        let body: &Quad = &snapshot.body;
+        let (x, y) = rasterizer.to_screen(body.center.x - body.width / 4., body.center.y);
+        assert_eq!(frame.pixel(x as usize, y as usize), PURPLE);
+        // drawing is a pure function of the snapshot
+        rasterizer.draw(&snapshot);
+        assert_eq!(rasterizer.frame(), &frame);
+    }
+}
@@ -0,0 +1,358 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+use uom::si::time::second;
+
+use crate::game::{DT, Game, Snapshot};
+use crate::rasterizer::{Frame, Rasterizer};
+use crate::renderer::Renderer;
+
+const TEXT_COLOR: [u8; 3] = [255, 255, 255];
+// glyphs are 3x5 pixels before scaling
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    // one animated file per episode
+    Gif,
+    // a directory of numbered frames per episode
+    Png,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    pub format: Format,
+    pub width: usize,
+    pub height: usize,
+    // step, reward, action and altitude in the top left corner
+    pub overlay: bool,
+    // training sessions between recorded greedy episodes, 0 records none.
+    // Only the DQN family, which reads the train table, records while training.
+    pub every: usize,
+    // relative paths are placed in the run's output directory
+    pub directory: PathBuf,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            format: Format::Gif,
+            width: 400,
+            height: 400,
+            overlay: true,
+            every: 0,
+            directory: PathBuf::from("recordings"),
+        }
+    }
+}
+
+impl RecordingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("recording width and height must be positive".to_string());
+        }
+        if self.format == Format::Gif && (self.width > u16::MAX as usize || self.height > u16::MAX as usize) {
+            return Err(format!("GIF frames are at most {} pixels wide and high", u16::MAX));
+        }
+        Ok(())
+    }
+
+    // whether to record after the given training session, counted from 1
+    pub fn is_due(&self, session: usize) -> bool {
+        self.every > 0 && session.is_multiple_of(self.every)
+    }
+
+    // file (GIF) or directory (PNG) of the named episode
+    pub fn episode_path(&self, name: &str) -> PathBuf {
+        match self.format {
+            Format::Gif => self.directory.join(format!("{}.gif", name)),
+            Format::Png => self.directory.join(name),
+        }
+    }
+}
+
+// What happened on the step that led to a frame.
+pub struct Telemetry {
+    pub action: String,
+    pub reward: f32,
+}
+
+// 3x5 bitmap font, one row per entry and the glyph's left pixel in bit 2.
+// Covers the overlay's labels and numbers; anything else draws as a space.
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    match character {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'N' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+// Draws text with its top left corner at (x, y), clipped to the frame.
+fn draw_text(frame: &mut Frame, text: &str, x: usize, y: usize, scale: usize) {
+    for (i, character) in text.chars().enumerate() {
+        let left: usize = x + i * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (px, py) = (left + column * scale + dx, y + row * scale + dy);
+                        if px < frame.width && py < frame.height {
+                            let i: usize = 3 * (py * frame.width + px);
+                            frame.pixels[i..i + 3].copy_from_slice(&TEXT_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn encoding_error(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::other(error)
+}
+
+// Collects rasterized frames of an episode and writes them out.
+pub struct Recorder {
+    config: RecordingConfig,
+    rasterizer: Rasterizer,
+    frames: Vec<Frame>,
+}
+
+impl Recorder {
+    pub fn new(config: &RecordingConfig) -> Self {
+        config.validate().unwrap();
+        Self {
+            rasterizer: Rasterizer::new(config.width, config.height),
+            config: config.clone(),
+            frames: vec![],
+        }
+    }
+
+    // telemetry is None for the episode's first frame, which no step led to
+    pub fn capture(&mut self, snapshot: &Snapshot, telemetry: Option<&Telemetry>) {
+        self.rasterizer.draw(snapshot);
+        let mut frame: Frame = self.rasterizer.frame().clone();
+        if self.config.overlay {
+            // one glyph pixel per frame pixel for every 100 rows
+            let scale: usize = (frame.height / 100).max(1);
+            let mut lines: Vec<String> = vec![format!("STEP {}", snapshot.steps)];
+            if let Some(telemetry) = telemetry {
+                lines.push(format!("REWARD {:.2}", telemetry.reward));
+                lines.push(format!("ACTION {}", telemetry.action));
+            }
+            lines.push(format!("ALT {:.2}", (snapshot.body.center.y - snapshot.ground).value));
+            for (i, line) in lines.iter().enumerate() {
+                draw_text(&mut frame, line, 2 * scale, (2 + i * (GLYPH_HEIGHT + 2)) * scale, scale);
+            }
+        }
+        self.frames.push(frame);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Writes the captured frames to path, see RecordingConfig::episode_path.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path: &Path = path.as_ref();
+        match self.config.format {
+            Format::Gif => self.save_gif(path),
+            Format::Png => self.save_png(path),
+        }
+    }
+
+    fn save_gif(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let (width, height) = (self.config.width as u16, self.config.height as u16);
+        let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width, height, &[])
+            .map_err(encoding_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(encoding_error)?;
+        // one step lasts DT, in the GIF's hundredths of a second
+        let delay: u16 = (DT.get::<second>() * 100.).round() as u16;
+        for frame in &self.frames {
+            let mut gif_frame = gif::Frame::from_rgb_speed(width, height, &frame.pixels, 10);
+            gif_frame.delay = delay;
+            encoder.write_frame(&gif_frame).map_err(encoding_error)?;
+        }
+        Ok(())
+    }
+
+    fn save_png(&self, directory: &Path) -> io::Result<()> {
+        fs::create_dir_all(directory)?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let file = BufWriter::new(File::create(directory.join(format!("frame_{:04}.png", i)))?);
+            let mut encoder = png::Encoder::new(file, frame.width as u32, frame.height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().map_err(encoding_error)?;
+            writer.write_image_data(&frame.pixels).map_err(encoding_error)?;
+            writer.finish().map_err(encoding_error)?;
+        }
+        Ok(())
+    }
+}
+
+// Plays one episode of game from its current state without a window,
+// recording it to path. Returns the episode's score.
+pub fn record_episode(
+    game: &mut Game,
+    mut choose: impl FnMut(&Array1<f32>) -> usize,
+    config: &RecordingConfig,
+    path: impl AsRef<Path>,
+) -> io::Result<f32> {
+    let mut recorder = Recorder::new(config);
+    let mut observation: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut score: f32 = 0.;
+    recorder.capture(&game.snapshot(), None);
+    loop {
+        game.observe(&mut observation);
+        let action: usize = choose(&observation);
+        let (reward, finished) = game.step(action);
+        score += reward;
+        recorder.capture(
+            &game.snapshot(),
+            Some(&Telemetry {
+                action: action.to_string(),
+                reward,
+            }),
+        );
+        if finished {
+            break;
+        }
+    }
+    recorder.save(path)?;
+    Ok(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::SEED;
+
+    fn temporary(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("recording_{}_{}", name, std::process::id()))
+    }
+
+    fn config(format: Format) -> RecordingConfig {
+        RecordingConfig {
+            format,
+            width: 24,
+            height: 20,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn episodes_are_due_every_few_sessions() {
+        let mut config = RecordingConfig::default();
+        assert!(!(1..=10).any(|session| config.is_due(session)));
+        config.every = 3;
+        let due: Vec<usize> = (1..=10).filter(|&session| config.is_due(session)).collect();
+        assert_eq!(due, vec![3, 6, 9]);
+        assert_eq!(config.episode_path("best"), PathBuf::from("recordings/best.gif"));
+        config.format = Format::Png;
+        assert_eq!(config.episode_path("best"), PathBuf::from("recordings/best"));
+        assert!(config.validate().is_ok());
+        config.width = 0;
+        assert!(config.validate().is_err());
+        config.width = u16::MAX as usize + 1;
+        assert!(config.validate().is_ok());
+        config.format = Format::Gif;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn the_overlay_draws_over_the_top_left_corner() {
+        let snapshot: Snapshot = Game::with_seed(SEED).snapshot();
+        let mut plain = Recorder::new(&RecordingConfig {
+            overlay: false,
+            ..config(Format::Gif)
+        });
+        let mut overlaid = Recorder::new(&config(Format::Gif));
+        let telemetry = Telemetry {
+            action: "1".to_string(),
+            reward: -0.5,
+        };
+        plain.capture(&snapshot, Some(&telemetry));
+        overlaid.capture(&snapshot, Some(&telemetry));
+        assert_eq!(overlaid.len(), 1);
+        let (plain, overlaid) = (&plain.frames[0], &overlaid.frames[0]);
+        // the S of STEP starts with a lit pixel two pixels in from the corner
+        assert_eq!(plain.pixel(3, 2), [0, 0, 0]);
+        assert_eq!(overlaid.pixel(3, 2), TEXT_COLOR);
+        assert_ne!(plain, overlaid);
+    }
+
+    #[test]
+    fn episodes_are_saved_as_gifs_or_numbered_pngs() {
+        let gif: PathBuf = temporary("episode.gif");
+        let mut game = Game::with_seed(SEED);
+        let mut steps: usize = 0;
+        record_episode(
+            &mut game,
+            |_| {
+                steps += 1;
+                steps % 2
+            },
+            &config(Format::Gif),
+            &gif,
+        )
+        .unwrap();
+        assert!(fs::read(&gif).unwrap().starts_with(b"GIF89a"));
+        fs::remove_file(&gif).unwrap();
+
+        let directory: PathBuf = temporary("episode");
+        let mut recorder = Recorder::new(&config(Format::Png));
+        assert!(recorder.is_empty());
+        let mut game = Game::with_seed(SEED);
+        recorder.capture(&game.snapshot(), None);
+        game.step(0);
+        recorder.capture(&game.snapshot(), None);
+        recorder.save(&directory).unwrap();
+        let mut files: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(files, vec!["frame_0000.png", "frame_0001.png"]);
+    }
+}
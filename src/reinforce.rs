@@ -0,0 +1,210 @@
+use ndarray::Array1;
+use rand::SeedableRng;
+use rand_distr::{Distribution, weighted::WeightedIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::model;
+
+// Hyperparameters of train, the reinforce table of an experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReinforceConfig {
+    pub iterations: u16,
+    pub episodes_per_update: u16,
+    pub gamma: f32,
+    pub policy_learning_rate: f32,
+    pub baseline_learning_rate: f32,
+    // credit every step with the return from there on, not the whole episode's
+    pub reward_to_go: bool,
+}
+
+impl Default for ReinforceConfig {
+    fn default() -> Self {
+        Self {
+            iterations: 50,
+            episodes_per_update: 8,
+            gamma: 0.99,
+            policy_learning_rate: 0.001,
+            baseline_learning_rate: 0.001,
+            reward_to_go: true,
+        }
+    }
+}
+
+impl ReinforceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.iterations == 0 || self.episodes_per_update == 0 {
+            return Err("iterations and episodes_per_update must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.gamma) {
+            return Err(format!("gamma must be in [0, 1], got {}", self.gamma));
+        }
+        if !(self.policy_learning_rate > 0. && self.baseline_learning_rate > 0.) {
+            return Err("policy_learning_rate and baseline_learning_rate must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub struct Episode {
+    pub states: Vec<Array1<f32>>,
+    pub actions: Vec<usize>,
+    pub rewards: Vec<f32>,
+}
+
+impl Episode {
+    pub fn score(&self) -> f32 {
+        self.rewards.iter().sum()
+    }
+}
+
+pub fn sample_action(policy: &mut model::Model, state: &Array1<f32>, rng: &mut rand::rngs::StdRng) -> usize {
+    let probabilities: Array1<f32> = model::softmax(policy.forward(state));
+    WeightedIndex::new(probabilities.iter()).unwrap().sample(rng)
+}
+
+// Plays one full episode from a fresh game, sampling actions from the softmax policy.
+pub fn collect_episode(
+    game: &mut crate::game::Game,
+    policy: &mut model::Model,
+    rng: &mut rand::rngs::StdRng,
+) -> Episode {
+    let mut episode = Episode {
+        states: vec![],
+        actions: vec![],
+        rewards: vec![],
+    };
+    let mut state: Array1<f32> = Array1::zeros(game.observation_space);
+    game.reset();
+    loop {
+        game.observe(&mut state);
+        let choice: usize = sample_action(policy, &state, rng);
+        let (reward, finished) = game.step(choice);
+        episode.states.push(state.clone());
+        episode.actions.push(choice);
+        episode.rewards.push(reward);
+        if finished {
+            game.reset();
+            return episode;
+        }
+    }
+}
+
+// Discounted return G_t for every step. Without reward-to-go every step is
+// credited with the return of the whole episode.
+pub fn discounted_returns(rewards: &[f32], gamma: f32, reward_to_go: bool) -> Vec<f32> {
+    let mut returns: Vec<f32> = vec![0.; rewards.len()];
+    let mut running: f32 = 0.;
+    for t in (0..rewards.len()).rev() {
+        running = rewards[t] + gamma * running;
+        returns[t] = running;
+    }
+    if !reward_to_go {
+        let total: f32 = returns.first().copied().unwrap_or(0.);
+        returns.fill(total);
+    }
+    returns
+}
+
+// d(-log pi(a|s) * advantage)/dlogits = (pi - onehot(a)) * advantage
+pub fn policy_gradient(probabilities: &Array1<f32>, action: usize, advantage: f32) -> Array1<f32> {
+    let mut derivative: Array1<f32> = probabilities.clone();
+    derivative[action] -= 1.;
+    derivative * advantage
+}
+
+// Monte-Carlo policy gradient. The policy outputs one logit per action; the
+// optional baseline outputs a single state value and is regressed on the returns.
+// Returns the mean score of every iteration.
+pub fn train(
+    game: &mut crate::game::Game,
+    policy: &mut model::Model,
+    mut baseline: Option<&mut model::Model>,
+    config: &ReinforceConfig,
+    seed: u64,
+) -> Vec<f32> {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut scores: Vec<f32> = vec![];
+    for iter in 0..config.iterations {
+        let mut total_score: f32 = 0.;
+        let mut steps: usize = 0;
+        let mut value_loss: f32 = 0.;
+        for _ in 0..config.episodes_per_update {
+            let episode: Episode = collect_episode(game, policy, &mut rng);
+            total_score += episode.score();
+            steps += episode.rewards.len();
+            let returns: Vec<f32> = discounted_returns(&episode.rewards, config.gamma, config.reward_to_go);
+            for (t, state) in episode.states.iter().enumerate() {
+                let advantage: f32 = match baseline.as_deref_mut() {
+                    Some(value) => {
+                        let prediction: f32 = value.forward(state)[0];
+                        value_loss += (prediction - returns[t]).powi(2);
+                        value.backprop(state, &Array1::from_elem(1, prediction - returns[t]));
+                        returns[t] - prediction
+                    }
+                    None => returns[t],
+                };
+                let probabilities: Array1<f32> = model::softmax(policy.forward(state));
+                policy.backprop(state, &policy_gradient(&probabilities, episode.actions[t], advantage));
+            }
+        }
+        // gradients were summed over every step, average them before stepping
+        policy.apply_gradients(config.policy_learning_rate / steps as f32);
+        if let Some(value) = baseline.as_deref_mut() {
+            value.apply_gradients(config.baseline_learning_rate / steps as f32);
+        }
+        let mean_score: f32 = total_score / config.episodes_per_update as f32;
+        println!(
+            "Iteration {}\tMean score: {}\tValue loss: {}",
+            iter,
+            mean_score,
+            value_loss / steps as f32
+        );
+        scores.push(mean_score);
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::SEED;
+
+    #[test]
+    fn returns_are_discounted_from_each_step_or_the_whole_episode() {
+        let rewards: [f32; 3] = [1., 1., 1.];
+        assert_eq!(discounted_returns(&rewards, 0.5, true), vec![1.75, 1.5, 1.]);
+        assert_eq!(discounted_returns(&rewards, 0.5, false), vec![1.75; 3]);
+        assert!(discounted_returns(&[], 0.5, false).is_empty());
+    }
+
+    #[test]
+    fn policy_gradient_favours_actions_with_positive_advantage() {
+        let probabilities: Array1<f32> = Array1::from(vec![0.25, 0.75]);
+        let derivative: Array1<f32> = policy_gradient(&probabilities, 1, 2.);
+        assert_eq!(derivative, Array1::from(vec![0.5, -0.5]));
+        // descending the derivative raises the chosen action's logit
+        assert!(derivative[1] < 0.);
+        assert_eq!(policy_gradient(&probabilities, 1, -2.), -derivative);
+    }
+
+    #[test]
+    fn training_scores_every_iteration_and_fits_the_baseline() {
+        let mut game = crate::game::Game::with_seed(SEED);
+        game.max_steps = 5;
+        let mut policy = model::Model::with_seed(SEED);
+        policy.add_layer(game.observation_space, game.action_space, false);
+        let mut baseline = model::Model::with_seed(SEED + 1);
+        baseline.add_layer(game.observation_space, 1, false);
+        let config = ReinforceConfig {
+            iterations: 3,
+            episodes_per_update: 2,
+            ..Default::default()
+        };
+        let initial: Array1<f32> = baseline.flat_parameters();
+        let scores: Vec<f32> = train(&mut game, &mut policy, Some(&mut baseline), &config, SEED);
+        assert_eq!(scores.len(), 3);
+        assert!(scores.iter().all(|score| score.is_finite()));
+        assert_ne!(baseline.flat_parameters(), initial);
+    }
+}
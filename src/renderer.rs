@@ -0,0 +1,221 @@
+use lazy_static::lazy_static;
+#[cfg(feature = "graphics")]
+use macroquad::color::{BLACK, BLUE, Color, GRAY, PURPLE, WHITE};
+#[cfg(feature = "graphics")]
+use macroquad::shapes::draw_rectangle;
+#[cfg(feature = "graphics")]
+use macroquad::window::clear_background;
+use uom::si::f32::*;
+use uom::si::length::meter;
+use uom::si::time::second;
+use uom::si::velocity::meter_per_second;
+
+use crate::game::{DT, Pos, Snapshot};
+#[cfg(feature = "graphics")]
+use crate::graphics;
+use crate::graphics::ENV_BOX_HEIGHT;
+#[cfg(feature = "graphics")]
+use crate::graphics::ENV_BOX_WIDTH;
+
+lazy_static! {
+    static ref PARTICLE_SPEED: Velocity =
+        Velocity::new::<meter_per_second>(ENV_BOX_HEIGHT.value / 5.);
+    pub static ref PARTICLE_RADIUS: Length = *ENV_BOX_HEIGHT / 100.;
+    static ref PARTICLE_LIFTIME: Time = Time::new::<second>(1.);
+}
+
+// Draws the scene described by a Game::snapshot.
+pub trait Renderer {
+    fn draw(&mut self, snapshot: &Snapshot);
+}
+
+#[derive(Clone)]
+struct JetParticle {
+    x: Length,
+    y: Length,
+    vx: Velocity,
+    vy: Velocity,
+    life: Time,
+}
+
+impl JetParticle {
+    // starts dead, so it stays invisible until activated
+    fn new() -> Self {
+        Self {
+            x: Length::new::<meter>(0.),
+            y: Length::new::<meter>(0.),
+            vx: Velocity::new::<meter_per_second>(0.),
+            vy: Velocity::new::<meter_per_second>(0.),
+            life: *PARTICLE_LIFTIME,
+        }
+    }
+
+    fn activate(&mut self, x: Length, y: Length, direction: Angle) {
+        self.x = x;
+        self.y = y;
+        self.life = Time::new::<second>(0.);
+        self.vx = *PARTICLE_SPEED * direction.cos();
+        self.vy = *PARTICLE_SPEED * direction.sin();
+    }
+
+    fn update(&mut self) {
+        self.x += self.vx * (*DT);
+        self.y += self.vy * (*DT);
+        self.life += *DT;
+    }
+}
+
+// Engine jets are a purely visual effect, so their state lives with the
+// renderer rather than the simulation. Advances once per simulation step.
+#[derive(Clone)]
+pub struct JetParticles {
+    particles: [JetParticle; 15],
+    index: usize,
+    steps: Option<u16>,
+}
+
+impl Default for JetParticles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JetParticles {
+    pub fn new() -> Self {
+        Self {
+            particles: core::array::from_fn(|_| JetParticle::new()),
+            index: 0,
+            steps: None,
+        }
+    }
+
+    // ages the particles and emits one from the snapshot's exhaust, unless
+    // this step was already seen (renderers may draw a step several times)
+    pub fn advance(&mut self, snapshot: &Snapshot) {
+        if self.steps == Some(snapshot.steps) {
+            return;
+        }
+        // a new episode
+        if self.steps.is_some_and(|steps| snapshot.steps < steps) {
+            *self = Self::new();
+        }
+        self.steps = Some(snapshot.steps);
+        for particle in &mut self.particles {
+            particle.update();
+        }
+        if let Some(exhaust) = snapshot.exhaust {
+            self.particles[self.index].activate(exhaust.pos.x, exhaust.pos.y, exhaust.direction);
+            self.index = (self.index + 1) % self.particles.len();
+        }
+    }
+
+    // live particles and their opacity, fading from 1 to 0 over their lifetime
+    pub fn visible(&self) -> impl Iterator<Item = (Pos, f32)> + '_ {
+        self.particles
+            .iter()
+            .filter(|particle| particle.life < *PARTICLE_LIFTIME)
+            .map(|particle| {
+                (
+                    Pos {
+                        x: particle.x,
+                        y: particle.y,
+                    },
+                    ((*PARTICLE_LIFTIME - particle.life) / *PARTICLE_LIFTIME).value,
+                )
+            })
+    }
+}
+
+// Draws into the current macroquad window.
+#[cfg(feature = "graphics")]
+#[derive(Default)]
+pub struct MacroquadRenderer {
+    jet_particles: JetParticles,
+}
+
+#[cfg(feature = "graphics")]
+impl MacroquadRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "graphics")]
+impl Renderer for MacroquadRenderer {
+    fn draw(&mut self, snapshot: &Snapshot) {
+        self.jet_particles.advance(snapshot);
+        clear_background(BLACK);
+        draw_rectangle(
+            0.,
+            *graphics::GRAPHICS_SCALAR * (*ENV_BOX_HEIGHT - snapshot.ground).value,
+            *graphics::GRAPHICS_SCALAR * ENV_BOX_WIDTH.value,
+            *graphics::GRAPHICS_SCALAR * snapshot.ground.value,
+            WHITE,
+        );
+        let body = &snapshot.body;
+        graphics::adjusted_draw_rectangle_ex(body.center.x, body.center.y, body.width, body.height, body.tilt, PURPLE);
+        for engine in &snapshot.engines {
+            graphics::adjusted_draw_rectangle_ex(
+                engine.center.x,
+                engine.center.y,
+                engine.width,
+                engine.height,
+                engine.tilt,
+                GRAY,
+            );
+        }
+        for (start, end) in &snapshot.legs {
+            graphics::adjusted_draw_line(start.x, start.y, end.x, end.y, BLUE);
+        }
+        for (pos, alpha) in self.jet_particles.visible() {
+            graphics::adjusted_draw_circle(pos.x, pos.y, *PARTICLE_RADIUS, Color::new(1., 1., 1., alpha));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::train::SEED;
+
+    #[test]
+    fn jet_particles_advance_once_per_step_and_restart_with_the_episode() {
+        let mut game = Game::with_seed(SEED);
+        let mut particles = JetParticles::new();
+        particles.advance(&game.snapshot());
+        assert_eq!(particles.visible().count(), 0);
+        game.step(0);
+        let snapshot: Snapshot = game.snapshot();
+        assert!(snapshot.exhaust.is_some());
+        // drawing a step twice must not emit twice
+        particles.advance(&snapshot);
+        particles.advance(&snapshot);
+        assert_eq!(particles.visible().count(), 1);
+        // a particle lives for one step of DT, so the next emission replaces it
+        game.step(1);
+        particles.advance(&game.snapshot());
+        let visible: Vec<(Pos, f32)> = particles.visible().collect();
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].1, 1.);
+        game.reset();
+        particles.advance(&game.snapshot());
+        assert_eq!(particles.visible().count(), 0);
+    }
+
+    // taking and drawing snapshots only reads the simulation
+    #[test]
+    fn rendering_does_not_change_the_simulation() {
+        let mut drawn = Game::with_seed(SEED);
+        let mut undrawn = Game::with_seed(SEED);
+        let mut particles = JetParticles::new();
+        for step in 0..20 {
+            particles.advance(&drawn.snapshot());
+            assert_eq!(drawn.step(step % 2), undrawn.step(step % 2));
+        }
+        let (drawn, undrawn) = (drawn.snapshot(), undrawn.snapshot());
+        assert_eq!(drawn.body.center.x, undrawn.body.center.x);
+        assert_eq!(drawn.body.center.y, undrawn.body.center.y);
+        assert_eq!(drawn.steps, undrawn.steps);
+    }
+}
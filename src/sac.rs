@@ -0,0 +1,270 @@
+use ndarray::{Array1, concatenate, s};
+use ndarray_rand::RandomExt;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::train::{BATCH_SIZE, ContinuousExperience, ReplayBuffer};
+use crate::{game, model};
+
+// Hyperparameters of train, the sac table of an experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SacConfig {
+    pub sessions: u16,
+    // uniformly random actions before the first update
+    pub warmup_steps: usize,
+    pub batch_size: usize,
+    pub gamma: f32,
+    // soft target update rate
+    pub tau: f32,
+    pub actor_learning_rate: f32,
+    pub critic_learning_rate: f32,
+    pub alpha_learning_rate: f32,
+    // starting entropy temperature, see SoftActorCritic::new
+    pub initial_alpha: f32,
+}
+
+impl Default for SacConfig {
+    fn default() -> Self {
+        Self {
+            sessions: 200,
+            warmup_steps: 50,
+            batch_size: BATCH_SIZE,
+            gamma: 0.99,
+            tau: 0.005,
+            actor_learning_rate: 0.0003,
+            critic_learning_rate: 0.0003,
+            alpha_learning_rate: 0.0003,
+            initial_alpha: 0.2,
+        }
+    }
+}
+
+impl SacConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sessions == 0 || self.batch_size == 0 {
+            return Err("sessions and batch_size must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.gamma) {
+            return Err(format!("gamma must be in [0, 1], got {}", self.gamma));
+        }
+        if !(self.tau > 0. && self.tau <= 1.) {
+            return Err(format!("tau must be in (0, 1], got {}", self.tau));
+        }
+        if !(self.actor_learning_rate > 0. && self.critic_learning_rate > 0. && self.alpha_learning_rate > 0.) {
+            return Err("actor, critic and alpha learning rates must be positive".to_string());
+        }
+        if !(self.initial_alpha > 0. && self.initial_alpha.is_finite()) {
+            return Err(format!("initial_alpha must be positive, got {}", self.initial_alpha));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct SoftActorCritic {
+    // should be squashed so actions land in [-1, 1]
+    pub actor: model::GaussianPolicy,
+    pub critics: Vec<model::Model>,
+    pub critic_targets: Vec<model::Model>,
+    // the temperature is learned in log space to stay positive
+    pub log_alpha: f32,
+    pub target_entropy: f32,
+}
+
+impl SoftActorCritic {
+    // The usual target entropy is -action_dims.
+    pub fn new(actor: model::GaussianPolicy, critics: Vec<model::Model>, initial_alpha: f32, target_entropy: f32) -> Self {
+        assert_eq!(critics.len(), 2, "SAC needs two critics");
+        Self {
+            actor,
+            critic_targets: critics.clone(),
+            critics,
+            log_alpha: initial_alpha.ln(),
+            target_entropy,
+        }
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.log_alpha.exp()
+    }
+
+    fn min_q(critics: &mut [model::Model], input: &Array1<f32>) -> (usize, f32) {
+        critics
+            .iter_mut()
+            .map(|critic| critic.forward(input)[0])
+            .enumerate()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap()
+    }
+
+    // soft Bellman target r + gamma * (min Q'(s', a') - alpha * log pi(a'|s'))
+    fn bellman_target(&mut self, experience: &ContinuousExperience, gamma: f32, rng: &mut rand::rngs::StdRng) -> f32 {
+        if experience.done {
+            return experience.reward;
+        }
+        let next: model::GaussianSample = self.actor.sample(&experience.next_state, rng);
+        let input: Array1<f32> = concatenate![ndarray::Axis(0), experience.next_state, next.action];
+        let (_, next_value) = Self::min_q(&mut self.critic_targets, &input);
+        experience.reward + gamma * (next_value - self.alpha() * next.log_prob)
+    }
+
+    fn update_critics(&mut self, batch: &[&ContinuousExperience], config: &SacConfig, rng: &mut rand::rngs::StdRng) -> f32 {
+        let mut loss: f32 = 0.;
+        for experience in batch {
+            let target: f32 = self.bellman_target(experience, config.gamma, rng);
+            let input: Array1<f32> = concatenate![ndarray::Axis(0), experience.state, experience.action];
+            for critic in &mut self.critics {
+                let error: f32 = critic.forward(&input)[0] - target;
+                loss += 0.5 * error * error;
+                critic.backprop(&input, &Array1::from_elem(1, error));
+            }
+        }
+        for critic in &mut self.critics {
+            critic.apply_gradients(config.critic_learning_rate / batch.len() as f32);
+        }
+        loss / (2 * batch.len()) as f32
+    }
+
+    // Minimises alpha * log pi(a|s) - min Q(s, a) through reparameterized
+    // samples, then steps the temperature. Returns (actor loss, mean log pi).
+    fn update_actor_and_alpha(
+        &mut self,
+        batch: &[&ContinuousExperience],
+        config: &SacConfig,
+        rng: &mut rand::rngs::StdRng,
+    ) -> (f32, f32) {
+        let alpha: f32 = self.alpha();
+        let mut loss: f32 = 0.;
+        let mut mean_log_prob: f32 = 0.;
+        for experience in batch {
+            let state_size: usize = experience.state.len();
+            let sample: model::GaussianSample = self.actor.sample(&experience.state, rng);
+            let input: Array1<f32> = concatenate![ndarray::Axis(0), experience.state, sample.action];
+            let (chosen, q_value) = Self::min_q(&mut self.critics, &input);
+            // re-forward the critic that attained the minimum so its activations match the input
+            let critic: &mut model::Model = &mut self.critics[chosen];
+            critic.forward(&input);
+            critic.backprop(&input, &Array1::from_elem(1, -1.));
+            let action_derivative: Array1<f32> = critic.input_derivative().slice(s![state_size..]).to_owned();
+            // sample() already forwarded this state through the actor
+            self.actor
+                .backprop_reparameterized(&experience.state, &sample, &action_derivative, alpha);
+            loss += alpha * sample.log_prob - q_value;
+            mean_log_prob += sample.log_prob;
+        }
+        for critic in &mut self.critics {
+            critic.zero_gradients();
+        }
+        self.actor.apply_gradients(config.actor_learning_rate / batch.len() as f32);
+        mean_log_prob /= batch.len() as f32;
+        // d/dlog_alpha of -log_alpha * (log pi + target_entropy)
+        self.log_alpha -= config.alpha_learning_rate * -(mean_log_prob + self.target_entropy);
+        (loss / batch.len() as f32, mean_log_prob)
+    }
+
+    fn update_targets(&mut self, tau: f32) {
+        for (target, critic) in self.critic_targets.iter_mut().zip(self.critics.iter()) {
+            target.soft_update(critic, tau);
+        }
+    }
+}
+
+// Off-policy loop on the continuous lander. Returns the score of every session.
+pub fn train(game: &mut game::Game, agent: &mut SoftActorCritic, config: &SacConfig, seed: u64) -> Vec<f32> {
+    let mut replay_buffer: ReplayBuffer<ContinuousExperience> = ReplayBuffer::new(seed);
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut state: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut steps: usize = 0;
+    let mut scores: Vec<f32> = vec![];
+    for _ in 0..config.sessions {
+        let mut score: f32 = 0.;
+        let mut critic_loss: f32 = 0.;
+        let mut actor_loss: f32 = 0.;
+        let mut log_prob: f32 = 0.;
+        loop {
+            game.observe(&mut state);
+            let acted_upon_state: Array1<f32> = state.clone();
+            let action: Array1<f32> = if steps < config.warmup_steps {
+                Array1::random_using(game.continuous_action_space, rand_distr::Uniform::new(-1., 1.).unwrap(), &mut rng)
+            } else {
+                agent.actor.sample(&state, &mut rng).action
+            };
+            let (reward, finished) = game.step_continuous(&action);
+            game.observe(&mut state);
+            replay_buffer.push_experience(ContinuousExperience {
+                state: acted_upon_state,
+                action,
+                reward,
+                next_state: state.clone(),
+                done: finished,
+            });
+            steps += 1;
+            if steps >= config.warmup_steps {
+                let batch: Vec<&ContinuousExperience> = replay_buffer.sample(config.batch_size);
+                critic_loss = agent.update_critics(&batch, config, &mut rng);
+                (actor_loss, log_prob) = agent.update_actor_and_alpha(&batch, config, &mut rng);
+                agent.update_targets(config.tau);
+            }
+            score += reward;
+            if finished {
+                game.reset();
+                println!(
+                    "Scored: {}\tCritic loss: {}\tActor loss: {}\tAlpha: {}\tEntropy: {}",
+                    score,
+                    critic_loss,
+                    actor_loss,
+                    agent.alpha(),
+                    -log_prob
+                );
+                break;
+            }
+        }
+        scores.push(score);
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::SEED;
+
+    fn agent(target_entropy: f32) -> SoftActorCritic {
+        let mut actor = model::Model::with_seed(SEED);
+        actor.add_layer(2, 2, false);
+        let critics: Vec<model::Model> = (1..=2)
+            .map(|i| {
+                let mut critic = model::Model::with_seed(SEED + i);
+                critic.add_layer(3, 1, false);
+                critic
+            })
+            .collect();
+        let actor = model::GaussianPolicy::new(actor, 1, model::LogStd::StateDependent, true);
+        SoftActorCritic::new(actor, critics, 0.2, target_entropy)
+    }
+
+    // alpha rises while the policy's entropy is below the target and falls above it
+    #[test]
+    fn temperature_tracks_the_target_entropy() {
+        let experience = ContinuousExperience {
+            state: Array1::from(vec![0.1, -0.3]),
+            action: Array1::from(vec![0.5]),
+            reward: 1.,
+            next_state: Array1::from(vec![0.2, -0.2]),
+            done: false,
+        };
+        let batch: Vec<&ContinuousExperience> = vec![&experience; 4];
+        let config = SacConfig::default();
+        let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut demanding: SoftActorCritic = agent(10.);
+        let mut lenient: SoftActorCritic = agent(-10.);
+        assert!((demanding.alpha() - config.initial_alpha).abs() < 1e-6);
+        for _ in 0..3 {
+            demanding.update_actor_and_alpha(&batch, &config, &mut rng);
+            lenient.update_actor_and_alpha(&batch, &config, &mut rng);
+        }
+        assert!(demanding.alpha() > config.initial_alpha);
+        assert!(lenient.alpha() < config.initial_alpha);
+    }
+}
@@ -0,0 +1,333 @@
+use ndarray::{Array1, Array2};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{game, model};
+
+// Hyperparameters of the tabular methods, the tabular table of an experiment
+// file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TabularConfig {
+    // range and bin count of each leading state vector feature, see Discretizer
+    pub low: Vec<f32>,
+    pub high: Vec<f32>,
+    pub bins: Vec<usize>,
+    pub episodes: u16,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    // epsilon-greedy exploration, decayed once per episode
+    pub initial_epsilon: f32,
+    pub epsilon_decay: f32,
+    pub min_epsilon: f32,
+}
+
+impl Default for TabularConfig {
+    fn default() -> Self {
+        Self {
+            // one bin per reachable position and per reachable unit velocity
+            low: vec![0., -0.36],
+            high: vec![2., 0.36],
+            bins: vec![13, 7],
+            episodes: 2000,
+            gamma: 0.99,
+            learning_rate: 0.1,
+            initial_epsilon: 1.0,
+            epsilon_decay: 0.998,
+            min_epsilon: 0.01,
+        }
+    }
+}
+
+impl TabularConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.low.len() != self.high.len() || self.high.len() != self.bins.len() {
+            return Err("low, high and bins must have one entry per feature".to_string());
+        }
+        if self.low.iter().zip(&self.high).any(|(low, high)| !(low < high && high.is_finite())) {
+            return Err("every low must be below its high".to_string());
+        }
+        if self.bins.contains(&0) {
+            return Err("bins must be positive".to_string());
+        }
+        if self.episodes == 0 {
+            return Err("episodes must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.gamma) {
+            return Err(format!("gamma must be in [0, 1], got {}", self.gamma));
+        }
+        if !(self.learning_rate > 0. && self.learning_rate <= 1.) {
+            return Err(format!("learning_rate must be in (0, 1], got {}", self.learning_rate));
+        }
+        for (name, value) in [
+            ("initial_epsilon", self.initial_epsilon),
+            ("epsilon_decay", self.epsilon_decay),
+            ("min_epsilon", self.min_epsilon),
+        ] {
+            if !(0. ..=1.).contains(&value) {
+                return Err(format!("{} must be in [0, 1], got {}", name, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn discretizer(&self) -> Discretizer {
+        Discretizer::new(
+            Array1::from(self.low.clone()),
+            Array1::from(self.high.clone()),
+            self.bins.clone(),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    QLearning,
+    Sarsa,
+    ExpectedSarsa,
+}
+
+// Maps a Rocket::to_vec observation to a single table row by splitting each
+// feature's [low, high] range into equal bins. Values outside are clamped.
+#[derive(Clone)]
+pub struct Discretizer {
+    pub low: Array1<f32>,
+    pub high: Array1<f32>,
+    pub bins: Vec<usize>,
+}
+
+impl Discretizer {
+    pub fn new(low: Array1<f32>, high: Array1<f32>, bins: Vec<usize>) -> Self {
+        assert!(low.len() == high.len() && high.len() == bins.len());
+        Self { low, high, bins }
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.bins.iter().product()
+    }
+
+    pub fn index(&self, observation: &Array1<f32>) -> usize {
+        let mut index: usize = 0;
+        for i in 0..self.bins.len() {
+            let fraction: f32 = (observation[i] - self.low[i]) / (self.high[i] - self.low[i]);
+            let bin: usize = ((fraction * self.bins[i] as f32) as isize).clamp(0, self.bins[i] as isize - 1) as usize;
+            index = index * self.bins[i] + bin;
+        }
+        index
+    }
+
+    // observation at the centre of a table row, inverse of index()
+    pub fn center(&self, mut index: usize) -> Array1<f32> {
+        let mut center: Array1<f32> = Array1::zeros(self.bins.len());
+        for i in (0..self.bins.len()).rev() {
+            let bin: usize = index % self.bins[i];
+            index /= self.bins[i];
+            let width: f32 = (self.high[i] - self.low[i]) / self.bins[i] as f32;
+            center[i] = self.low[i] + (bin as f32 + 0.5) * width;
+        }
+        center
+    }
+}
+
+#[derive(Clone)]
+pub struct TabularAgent {
+    pub discretizer: Discretizer,
+    pub method: Method,
+    pub q_table: Array2<f32>,
+    pub visits: Array2<u32>,
+    pub epsilon: f32,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    rng: rand::rngs::StdRng,
+}
+
+impl TabularAgent {
+    // seed drives the epsilon-greedy exploration
+    pub fn new(discretizer: Discretizer, action_space: usize, method: Method, config: &TabularConfig, seed: u64) -> Self {
+        let states: usize = discretizer.num_states();
+        Self {
+            discretizer,
+            method,
+            q_table: Array2::zeros((states, action_space)),
+            visits: Array2::zeros((states, action_space)),
+            epsilon: config.initial_epsilon,
+            gamma: config.gamma,
+            learning_rate: config.learning_rate,
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn greedy_action(&self, observation: &Array1<f32>) -> usize {
+        model::argmax(&self.q_table.row(self.discretizer.index(observation)).to_owned())
+    }
+
+    pub fn epsilon_greedy(&mut self, observation: &Array1<f32>) -> usize {
+        if self.rng.random::<f32>() < self.epsilon {
+            self.rng.random_range(0..self.q_table.ncols())
+        } else {
+            self.greedy_action(observation)
+        }
+    }
+
+    // value of the next state under the method's bootstrap rule
+    fn next_value(&self, next_row: usize, next_action: usize) -> f32 {
+        let row = self.q_table.row(next_row);
+        match self.method {
+            Method::QLearning => row.fold(f32::NEG_INFINITY, |acc, &q| acc.max(q)),
+            Method::Sarsa => row[next_action],
+            Method::ExpectedSarsa => {
+                // expectation under the epsilon-greedy behaviour policy
+                let actions: usize = row.len();
+                let greedy: usize = model::argmax(&row.to_owned());
+                let mut expected: f32 = 0.;
+                for (a, q) in row.iter().enumerate() {
+                    let mut probability: f32 = self.epsilon / actions as f32;
+                    if a == greedy {
+                        probability += 1. - self.epsilon;
+                    }
+                    expected += probability * q;
+                }
+                expected
+            }
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        observation: &Array1<f32>,
+        action: usize,
+        reward: f32,
+        next_observation: &Array1<f32>,
+        next_action: usize,
+        done: bool,
+    ) -> f32 {
+        let row: usize = self.discretizer.index(observation);
+        let target: f32 = if done {
+            reward
+        } else {
+            reward + self.gamma * self.next_value(self.discretizer.index(next_observation), next_action)
+        };
+        let error: f32 = target - self.q_table[(row, action)];
+        self.q_table[(row, action)] += self.learning_rate * error;
+        self.visits[(row, action)] += 1;
+        error
+    }
+
+    // V(s) = max_a Q(s, a) for every table row
+    pub fn value_function(&self) -> Array1<f32> {
+        self.q_table
+            .rows()
+            .into_iter()
+            .map(|row| row.fold(f32::NEG_INFINITY, |acc, &q| acc.max(q)))
+            .collect()
+    }
+
+    // Mean absolute difference between a Q-network and the table over every
+    // visited (state, action) cell, evaluated at the bin centres.
+    pub fn q_value_error(&self, network: &mut model::Model) -> f32 {
+        let mut total: f32 = 0.;
+        let mut cells: u32 = 0;
+        for row in 0..self.q_table.nrows() {
+            let prediction: Array1<f32> = network.forward(&self.discretizer.center(row)).clone();
+            for action in 0..self.q_table.ncols() {
+                if self.visits[(row, action)] > 0 {
+                    total += (prediction[action] - self.q_table[(row, action)]).abs();
+                    cells += 1;
+                }
+            }
+        }
+        total / cells.max(1) as f32
+    }
+}
+
+// Returns the score of every episode.
+pub fn train(game: &mut game::Game, agent: &mut TabularAgent, config: &TabularConfig) -> Vec<f32> {
+    let mut observation: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut next_observation: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut scores: Vec<f32> = vec![];
+    for episode in 0..config.episodes {
+        let mut score: f32 = 0.;
+        game.reset();
+        game.observe(&mut observation);
+        let mut action: usize = agent.epsilon_greedy(&observation);
+        loop {
+            let (reward, finished) = game.step(action);
+            game.observe(&mut next_observation);
+            // SARSA bootstraps from the action that will actually be taken next
+            let next_action: usize = agent.epsilon_greedy(&next_observation);
+            agent.update(&observation, action, reward, &next_observation, next_action, finished);
+            score += reward;
+            if finished {
+                break;
+            }
+            observation.assign(&next_observation);
+            action = next_action;
+        }
+        agent.epsilon = (agent.epsilon * config.epsilon_decay).max(config.min_epsilon);
+        if episode.is_multiple_of((config.episodes / 10).max(1)) {
+            println!("Episode {}\tScore: {}\tEpsilon: {}", episode, score, agent.epsilon);
+        }
+        scores.push(score);
+    }
+    game.reset();
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(method: Method, seed: u64) -> TabularAgent {
+        let discretizer = Discretizer::new(Array1::from(vec![0., -1.]), Array1::from(vec![1., 1.]), vec![4, 4]);
+        TabularAgent::new(discretizer, 2, method, &TabularConfig::default(), seed)
+    }
+
+    #[test]
+    fn exploration_follows_the_seed() {
+        let observation: Array1<f32> = Array1::zeros(2);
+        let choices = |seed: u64| -> Vec<usize> {
+            let mut agent: TabularAgent = agent(Method::QLearning, seed);
+            (0..64).map(|_| agent.epsilon_greedy(&observation)).collect()
+        };
+        assert_eq!(choices(1), choices(1));
+        assert_ne!(choices(1), choices(2));
+    }
+
+    #[test]
+    fn discretizer_centers_map_back_to_their_rows() {
+        let discretizer: Discretizer = agent(Method::QLearning, 0).discretizer;
+        for row in 0..discretizer.num_states() {
+            assert_eq!(discretizer.index(&discretizer.center(row)), row);
+        }
+        // values outside the range fall in the edge bins
+        assert_eq!(discretizer.index(&Array1::from(vec![-5., 5.])), 3);
+    }
+
+    // Updates Q(first row, action 0) towards a next state whose values are
+    // [1, 3], returning the change.
+    fn update_towards(method: Method, next_action: usize, done: bool) -> f32 {
+        let mut agent: TabularAgent = agent(method, 0);
+        agent.epsilon = 0.5;
+        let observation: Array1<f32> = Array1::from(vec![0.1, -0.9]);
+        let next_observation: Array1<f32> = Array1::from(vec![0.9, 0.9]);
+        let next_row: usize = agent.discretizer.index(&next_observation);
+        agent.q_table[(next_row, 0)] = 1.;
+        agent.q_table[(next_row, 1)] = 3.;
+        agent.update(&observation, 0, 0., &next_observation, next_action, done);
+        assert_eq!(agent.visits[(agent.discretizer.index(&observation), 0)], 1);
+        agent.q_table[(agent.discretizer.index(&observation), 0)]
+    }
+
+    #[test]
+    fn methods_bootstrap_from_their_own_next_value() {
+        let config = TabularConfig::default();
+        let step = |next_value: f32| config.learning_rate * config.gamma * next_value;
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+        // Q-learning takes the greedy value whatever action comes next
+        assert!(close(update_towards(Method::QLearning, 0, false), step(3.)));
+        assert!(close(update_towards(Method::Sarsa, 0, false), step(1.)));
+        // epsilon 0.5 over two actions: 0.25 * 1 + 0.75 * 3
+        assert!(close(update_towards(Method::ExpectedSarsa, 0, false), step(2.5)));
+        assert_eq!(update_towards(Method::QLearning, 0, true), 0.);
+    }
+}
@@ -1,6 +1,6 @@
 use std::vec;
 
-use ndarray::{Array1, Array2};
+use ndarray::{Array1, ArrayView2, Ix2};
 
 use crate::{game, model};
 
@@ -21,7 +21,7 @@ pub fn test_backprop() {
     agent.add_layer(2, 2, false);
     let mut target: model::Model = agent.clone();
     for iter in 0..ITERS {
-        game.state.to_vec(&mut state);
+        game.observe(&mut state);
         let mut loss_derivative: Array1<f32> = Array1::zeros(2);
         let agent_prediction: Array1<f32> = agent.forward(&state).clone();
         let agent_choice: usize = agent_prediction
@@ -31,7 +31,7 @@ pub fn test_backprop() {
             .unwrap();
         state_clone = state.clone();
         let (reward, finished) = game.step(agent_choice);
-        game.state.to_vec(&mut state);
+        game.observe(&mut state);
         let target_prediction: &Array1<f32> = target.forward(&state);
         let target_choice: usize = target_prediction
             .indexed_iter()
@@ -49,12 +49,14 @@ pub fn test_backprop() {
                 print!("Target output: ");
                 print_array(&target_prediction);
                 println!("\nWeights, weight gradients");
+                let parameters = agent.layers[0].parameters();
+                let gradients = agent.layers[0].gradients();
                 print_mats(vec![
-                    &agent.layers[0].weights,
-                    &agent.layers[0].weight_gradient,
+                    parameters[0].view().into_dimensionality::<Ix2>().unwrap(),
+                    gradients[0].view().into_dimensionality::<Ix2>().unwrap(),
                 ]);
                 println!("Biases, loss");
-                print_array(&agent.layers[0].biases);
+                print_array(&parameters[1].to_owned().into_dimensionality().unwrap());
                 print_array(&loss_derivative);
                 print!(
                     "\n====================================================================================================\n"
@@ -96,7 +98,7 @@ fn print_array(array: &Array1<f32>) {
 }
 
 // must be of same dims
-fn print_mats(mats: Vec<&Array2<f32>>) {
+fn print_mats(mats: Vec<ArrayView2<f32>>) {
     for row in 0..mats[0].nrows() {
         for mat in mats.iter() {
             print!("[");
@@ -1,115 +1,395 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::vec;
 
 use ndarray::Array1;
 use rand::{Rng, SeedableRng};
 use rand_distr::Distribution;
+use serde::{Deserialize, Serialize};
 
-use crate::{game, train};
+use crate::recording::{self, RecordingConfig};
+use crate::transitions::{Record, TransitionReader, TransitionWriter};
+use crate::{imitation, model};
 
-const SESSIONS: u16 = 20;
 const ITER_DISPLAY_PRECISION: u16 = 20;
-const LOG_INTERVAL: u16 = SESSIONS / ITER_DISPLAY_PRECISION;
-const GAMMA: f32 = 0.99;
-const LEARNING_RATE: f32 = 0.001;
-const BATCH_SIZE: usize = 4;
-const SAMPLING_FREQUENCY: usize = 5;
-const TARGET_UPDATE_FREQUENCY: usize = 3;
-const EPSILON_DECAY: f32 = 0.95;
 pub const SEED: u64 = 42;
+// default of TrainConfig::batch_size, DdpgConfig::batch_size and SacConfig::batch_size
+pub const BATCH_SIZE: usize = 4;
+// default of TrainConfig::dataset_path
+pub const DATASET_PATH: &str = "transitions.bin";
 
+// Hyperparameters of train, resume, train_from_demonstrations and the
+// distributional agents' distributional::train. Override
+// single fields with TrainConfig { sessions: 5, ..Default::default() }, or
+// through the train table of an experiment file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainConfig {
+    pub sessions: u16,
+    pub gamma: f32,
+    pub learning_rate: f32,
+    pub batch_size: usize,
+    // steps between minibatch updates
+    pub sampling_frequency: usize,
+    // updates between target network copies
+    pub target_update_frequency: usize,
+    // ignored by noisy networks, which explore through their weights
+    pub initial_epsilon: f32,
+    pub epsilon_decay: f32,
+    pub seed: u64,
+    // the replay buffer is dumped here every save_every sessions and after
+    // the last one, for resuming an interrupted run or as an offline dataset
+    pub dataset_path: PathBuf,
+    // 0 never dumps the replay buffer
+    pub save_every: u16,
+    // DQfD: supervised minibatches on the demonstrations before interacting
+    pub pretraining_updates: usize,
+    // chance that a sampled slot is drawn from the reserved demonstrations
+    pub demonstration_fraction: f32,
+    // how far the demonstrated action's value must exceed every other action's
+    pub margin: f32,
+    pub margin_loss_weight: f32,
+    // greedy episodes recorded every few sessions, never by default
+    pub recording: RecordingConfig,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            sessions: 20,
+            gamma: 0.99,
+            learning_rate: 0.001,
+            batch_size: BATCH_SIZE,
+            sampling_frequency: 5,
+            target_update_frequency: 3,
+            initial_epsilon: 1.0,
+            epsilon_decay: 0.95,
+            seed: SEED,
+            dataset_path: PathBuf::from(DATASET_PATH),
+            save_every: 5,
+            pretraining_updates: 1000,
+            demonstration_fraction: 0.25,
+            margin: 0.8,
+            margin_loss_weight: 1.0,
+            recording: RecordingConfig::default(),
+        }
+    }
+}
+
+impl TrainConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sessions == 0 {
+            return Err("sessions must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.gamma) {
+            return Err(format!("gamma must be in [0, 1], got {}", self.gamma));
+        }
+        if !(self.learning_rate > 0. && self.learning_rate.is_finite()) {
+            return Err(format!("learning_rate must be positive, got {}", self.learning_rate));
+        }
+        if self.batch_size == 0 || self.sampling_frequency == 0 || self.target_update_frequency == 0 {
+            return Err("batch_size, sampling_frequency and target_update_frequency must be positive".to_string());
+        }
+        if !(0. ..=1.).contains(&self.initial_epsilon) {
+            return Err(format!("initial_epsilon must be in [0, 1], got {}", self.initial_epsilon));
+        }
+        if !(self.epsilon_decay > 0. && self.epsilon_decay <= 1.) {
+            return Err(format!("epsilon_decay must be in (0, 1], got {}", self.epsilon_decay));
+        }
+        if !(0. ..=1.).contains(&self.demonstration_fraction) {
+            return Err(format!(
+                "demonstration_fraction must be in [0, 1], got {}",
+                self.demonstration_fraction
+            ));
+        }
+        if !(self.margin >= 0. && self.margin_loss_weight >= 0.) {
+            return Err("margin and margin_loss_weight must not be negative".to_string());
+        }
+        self.recording.validate()?;
+        Ok(())
+    }
+
+    // whether the replay buffer is dumped after the session-th session
+    pub fn is_save_due(&self, session: u16) -> bool {
+        self.save_every > 0 && (session.is_multiple_of(self.save_every) || session == self.sessions)
+    }
+
+    pub(crate) fn validated(&self) -> &Self {
+        if let Err(error) = self.validate() {
+            panic!("Invalid training config: {}", error);
+        }
+        self
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Experience {
+    pub(crate) state: Array1<f32>,
+    pub(crate) action: usize,
+    pub(crate) reward: f32,
+    pub(crate) next_state: Array1<f32>,
+    pub(crate) done: bool,
+}
+
+// transition with a continuous action vector
 #[derive(Clone)]
-struct Experience {
-    state: Array1<f32>,
-    action: usize,
-    reward: f32,
-    next_state: Array1<f32>,
+pub(crate) struct ContinuousExperience {
+    pub(crate) state: Array1<f32>,
+    pub(crate) action: Array1<f32>,
+    pub(crate) reward: f32,
+    pub(crate) next_state: Array1<f32>,
+    pub(crate) done: bool,
 }
 
-struct ReplayBuffer {
-    experience_replay: Vec<Experience>,
+pub(crate) struct ReplayBuffer<T> {
+    experience_replay: Vec<T>,
+    // reserved part that is never mixed with or displaced by the agent's own experience
+    demonstrations: Vec<T>,
     rng: rand::rngs::StdRng,
     sample_distr: rand_distr::Normal<f32>,
 }
 
-impl ReplayBuffer {
-    pub fn new() -> Self {
+impl<T> ReplayBuffer<T> {
+    pub fn new(seed: u64) -> Self {
         Self {
             experience_replay: vec![],
-            rng: rand::rngs::StdRng::seed_from_u64(train::SEED),
+            demonstrations: vec![],
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
             sample_distr: rand_distr::Normal::new(0., 0.).unwrap(),
         }
     }
 
-    pub fn push_experience(&mut self, experience: Experience) {
+    pub fn push_experience(&mut self, experience: T) {
         self.experience_replay.push(experience);
     }
 
-    pub fn sample(&mut self) -> [&Experience; BATCH_SIZE] {
+    pub fn push_demonstration(&mut self, experience: T) {
+        self.demonstrations.push(experience);
+    }
+
+    pub fn sample(&mut self, batch_size: usize) -> Vec<&T> {
+        self.sample_with_source(batch_size, 0.)
+            .into_iter()
+            .map(|(experience, _)| experience)
+            .collect()
+    }
+
+    // Like sample, also telling whether each experience is a demonstration.
+    // The reserved part is drawn from with the given probability, or always
+    // while the agent has no experience of its own.
+    pub fn sample_with_source(&mut self, batch_size: usize, demonstration_fraction: f32) -> Vec<(&T, bool)> {
         self.sample_distr =
             rand_distr::Normal::new(0., self.experience_replay.len() as f32 / 4.).unwrap();
-        let experiences: [&Experience; BATCH_SIZE] = (0..BATCH_SIZE)
+        (0..batch_size)
             .map(|_| {
-                &self.experience_replay[self.sample_distr.sample(&mut self.rng).abs().clamp(0., self.experience_replay.len() as f32) as usize]
+                let from_demonstrations: bool = !self.demonstrations.is_empty()
+                    && (self.experience_replay.is_empty() || self.rng.random::<f32>() < demonstration_fraction);
+                if from_demonstrations {
+                    (&self.demonstrations[self.rng.random_range(0..self.demonstrations.len())], true)
+                } else {
+                    (&self.experience_replay[self.sample_distr.sample(&mut self.rng).abs().clamp(0., (self.experience_replay.len() - 1) as f32) as usize], false)
+                }
             })
-            .collect::<Vec<&Experience>>()
-            .try_into()
-            .unwrap_or_else(|_| panic!("Failed to collect experiences into an array"));
-        return experiences;
+            .collect()
+    }
+}
+
+impl ReplayBuffer<Experience> {
+    // Streams the buffer to a binary transition file, demonstrations first.
+    // Writes next to the target and renames, so an interruption while saving
+    // leaves the previous file intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path: &Path = path.as_ref();
+        let mut temporary: PathBuf = path.to_path_buf();
+        temporary.as_mut_os_string().push(".tmp");
+        let state_dims: usize = self
+            .demonstrations
+            .iter()
+            .chain(self.experience_replay.iter())
+            .next()
+            .map_or(0, |experience| experience.state.len());
+        let mut writer: TransitionWriter = TransitionWriter::create(&temporary, state_dims, false)?;
+        for experience in &self.demonstrations {
+            writer.write(experience, true, None)?;
+        }
+        for experience in &self.experience_replay {
+            writer.write(experience, false, None)?;
+        }
+        writer.finish()?;
+        fs::rename(&temporary, path)
+    }
+
+    // Restores a saved buffer, demonstrations back into the reserved part.
+    pub fn load(path: impl AsRef<Path>, seed: u64) -> io::Result<Self> {
+        let mut replay_buffer: Self = Self::new(seed);
+        for record in TransitionReader::open(path)? {
+            let record = record?;
+            if record.demonstration {
+                replay_buffer.push_demonstration(record.experience);
+            } else {
+                replay_buffer.push_experience(record.experience);
+            }
+        }
+        Ok(replay_buffer)
     }
 }
 
-pub async fn train(game: &mut crate::game::Game, agent: &mut crate::model::Model) {
-    let mut replay_buffer: ReplayBuffer = ReplayBuffer::new();
-    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(SEED);
+// every record of a saved buffer, demonstrations included
+pub(crate) fn load_transitions(path: impl AsRef<Path>) -> io::Result<Vec<Record>> {
+    TransitionReader::open(path)?.collect()
+}
+
+// Demonstrations as replay transitions, the next state being the following
+// recorded observation of the same episode.
+fn demonstration_experiences(demonstrations: &[imitation::Demonstration]) -> Vec<Experience> {
+    demonstrations
+        .iter()
+        .enumerate()
+        .map(|(i, demonstration)| Experience {
+            state: demonstration.observation.clone(),
+            action: demonstration.action,
+            reward: demonstration.reward,
+            next_state: match demonstrations.get(i + 1) {
+                Some(next) if !demonstration.done => next.observation.clone(),
+                _ => demonstration.observation.clone(),
+            },
+            done: demonstration.done || i + 1 == demonstrations.len(),
+        })
+        .collect()
+}
+
+// One minibatch of TD updates. Demonstrations also get DQfD's large-margin
+// loss, max_a [Q(s, a) + margin(a)] - Q(s, a_demo), which pushes the
+// demonstrated action above every other one. Returns the batch's margin loss.
+fn learn(
+    agent: &mut model::Model,
+    target: &mut model::Model,
+    replay_buffer: &mut ReplayBuffer<Experience>,
+    config: &TrainConfig,
+) -> f32 {
+    let mut margin_loss: f32 = 0.;
+    target.resample_noise();
+    for (experience, demonstration) in replay_buffer.sample_with_source(config.batch_size, config.demonstration_fraction) {
+        let mut loss_derivative: Array1<f32> = Array1::zeros(agent.output_size());
+        let prediction: Array1<f32> = agent.forward(&experience.state).clone();
+        // terminal transitions do not bootstrap
+        let next_state_reward_prediction: f32 = if experience.done {
+            0.
+        } else {
+            *target
+                .forward(&experience.next_state)
+                .iter()
+                .max_by(|a, b| a.partial_cmp(b).unwrap())
+                .unwrap()
+        };
+        loss_derivative[experience.action] = prediction[experience.action]
+            - (experience.reward + config.gamma * next_state_reward_prediction);
+        if demonstration {
+            let mut margins: Array1<f32> = prediction.clone() + config.margin;
+            margins[experience.action] -= config.margin;
+            let best: usize = model::argmax(&margins);
+            margin_loss += margins[best] - prediction[experience.action];
+            loss_derivative[best] += config.margin_loss_weight;
+            loss_derivative[experience.action] -= config.margin_loss_weight;
+        }
+        agent.backprop(&experience.state, &loss_derivative);
+    }
+    agent.apply_gradients(config.learning_rate);
+    margin_loss
+}
+
+// Returns the score of every episode.
+pub fn train(game: &mut crate::game::Game, agent: &mut crate::model::Model, config: &TrainConfig) -> Vec<f32> {
+    let config: &TrainConfig = config.validated();
+    interact(game, agent, ReplayBuffer::new(config.seed), config)
+}
+
+// Continues from the replay buffer an interrupted run left at the config's
+// dataset_path, skipping the warm-up of refilling it.
+pub fn resume(
+    game: &mut crate::game::Game,
+    agent: &mut crate::model::Model,
+    config: &TrainConfig,
+) -> io::Result<Vec<f32>> {
+    let config: &TrainConfig = config.validated();
+    let replay_buffer: ReplayBuffer<Experience> = ReplayBuffer::load(&config.dataset_path, config.seed)?;
+    Ok(interact(game, agent, replay_buffer, config))
+}
+
+// DQfD warm start: pretrain on the demonstrations alone, then keep them in the
+// reserved part of the replay buffer while training continues as in train.
+pub fn train_from_demonstrations(
+    game: &mut crate::game::Game,
+    agent: &mut crate::model::Model,
+    demonstrations: &[imitation::Demonstration],
+    config: &TrainConfig,
+) -> Vec<f32> {
+    let config: &TrainConfig = config.validated();
+    assert!(!demonstrations.is_empty(), "DQfD needs at least one demonstration");
+    let mut replay_buffer: ReplayBuffer<Experience> = ReplayBuffer::new(config.seed);
+    for experience in demonstration_experiences(demonstrations) {
+        replay_buffer.push_demonstration(experience);
+    }
+    let mut target: crate::model::Model = agent.clone();
+    for update in 1..=config.pretraining_updates {
+        let margin_loss: f32 = learn(agent, &mut target, &mut replay_buffer, config);
+        if update.is_multiple_of(config.target_update_frequency) {
+            target = agent.clone();
+        }
+        if update.is_multiple_of((config.pretraining_updates / 10).max(1)) {
+            println!(
+                "Pretraining update {}\tMargin loss: {}",
+                update,
+                margin_loss / config.batch_size as f32
+            );
+        }
+    }
+    interact(game, agent, replay_buffer, config)
+}
+
+fn interact(
+    game: &mut crate::game::Game,
+    agent: &mut crate::model::Model,
+    mut replay_buffer: ReplayBuffer<Experience>,
+    config: &TrainConfig,
+) -> Vec<f32> {
+    let mut rng: rand::rngs::StdRng = rand::rngs::StdRng::seed_from_u64(config.seed);
     let mut acted_upon_state: Array1<f32>;
     let mut target: crate::model::Model = agent.clone();
     let mut state: Array1<f32> = Array1::zeros(game.observation_space);
-    let mut epsilon: f32 = 1.0;
+    // noisy networks explore through their weights, so no epsilon schedule is needed
+    let mut epsilon: f32 = if agent.is_noisy() { 0. } else { config.initial_epsilon };
     let mut sample_progress: usize = 0;
-    let mut loss_derivative: Array1<f32>;
-    for iter in 0..SESSIONS {
-        epsilon *= EPSILON_DECAY;
+    let mut scores: Vec<f32> = vec![];
+    for iter in 0..config.sessions {
+        epsilon *= config.epsilon_decay;
         let mut score: f32 = 0.;
         loop {
-            loss_derivative = Array1::zeros(game.action_space);
-            game.state().to_vec(&mut state);
+            game.observe(&mut state);
             acted_upon_state = state.clone();
+            agent.resample_noise();
             let agent_prediction = agent.forward(&state);
             let choice: usize = if rng.random::<f32>() > epsilon {
                 println!("Debug time");
                 dbg!(agent_prediction);
-                agent_prediction
-                    .indexed_iter()
-                    .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                    .map(|(i, _)| i)
-                    .unwrap()
+                model::argmax(agent_prediction)
             } else {
                 rng.random_range(0..game.action_space)
             };
             let (reward, finished) = game.step(choice);
-            game.state().to_vec(&mut state);
+            game.observe(&mut state);
             replay_buffer.push_experience(Experience {
                 state: acted_upon_state,
                 action: choice,
-                reward: reward,
+                reward,
                 next_state: state.clone(),
+                done: finished,
             });
             sample_progress += 1;
-            if sample_progress % SAMPLING_FREQUENCY == 0 {
-                for experience in replay_buffer.sample() {
-                    let agent_reward_prediction: f32 =
-                        agent.forward(&experience.state)[experience.action];
-                    let next_state_reward_prediction = target
-                        .forward(&experience.next_state)
-                        .iter()
-                        .max_by(|a, b| a.partial_cmp(b).unwrap())
-                        .unwrap();
-                    loss_derivative[experience.action] = agent_reward_prediction
-                        - GAMMA * (experience.reward + next_state_reward_prediction);
-                    agent.backprop(&experience.state, &loss_derivative);
-                }
-                agent.apply_gradients(LEARNING_RATE);
-                if sample_progress % (SAMPLING_FREQUENCY * TARGET_UPDATE_FREQUENCY) == 0 {
+            if sample_progress.is_multiple_of(config.sampling_frequency) {
+                learn(agent, &mut target, &mut replay_buffer, config);
+                if sample_progress.is_multiple_of(config.sampling_frequency * config.target_update_frequency) {
                     target = agent.clone();
                 }
             }
@@ -117,30 +397,58 @@ pub async fn train(game: &mut crate::game::Game, agent: &mut crate::model::Model
             if finished {
                 game.reset();
                 println!("Scored: {}", score);
+                scores.push(score);
                 break;
             }
         }
-        display_progress(iter);
+        if config.is_save_due(iter + 1)
+            && let Err(error) = replay_buffer.save(&config.dataset_path)
+        {
+            // a failed dump costs the dataset, not the training run
+            eprintln!(
+                "Failed to save replay buffer to {}: {}",
+                config.dataset_path.display(),
+                error
+            );
+        }
+        if config.recording.is_due(iter as usize + 1) {
+            agent.set_training(false);
+            record_greedy_episode(
+                game,
+                |state| model::argmax(agent.forward(state)),
+                &config.recording,
+                iter + 1,
+            );
+            agent.set_training(true);
+        }
+        display_progress(iter, config.sessions);
     }
-    game::run_game(
-        || {
-            game.state().to_vec(&mut state);
-            agent
-                .forward(&state)
-                .indexed_iter()
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                .map(|(i, _)| i)
-                .unwrap()
-        },
-    )
-    .await;
+    agent.set_training(false);
+    scores
+}
+
+// Records the greedy policy choose on a copy of the game, so training's
+// episodes are unaffected.
+pub(crate) fn record_greedy_episode(
+    game: &crate::game::Game,
+    choose: impl FnMut(&Array1<f32>) -> usize,
+    config: &RecordingConfig,
+    session: u16,
+) {
+    let mut episode: crate::game::Game = game.clone();
+    episode.log_trajectories(None);
+    episode.reset();
+    let path: PathBuf = config.episode_path(&format!("session_{}", session));
+    let score: f32 = recording::record_episode(&mut episode, choose, config, &path).expect("Failed to record episode");
+    println!("Recorded session {} (score {}) to {}", session, score, path.display());
 }
 
-fn display_progress(iter: u16) {
-    if LOG_INTERVAL == 0 || iter % LOG_INTERVAL != 0 {
+fn display_progress(iter: u16, sessions: u16) {
+    let log_interval: u16 = sessions / ITER_DISPLAY_PRECISION;
+    if log_interval == 0 || !iter.is_multiple_of(log_interval) {
         return;
     }
-    let hashtags: u16 = iter / LOG_INTERVAL;
+    let hashtags: u16 = iter / log_interval;
     let spaces: u16 = ITER_DISPLAY_PRECISION - hashtags;
     print!("[");
     for _ in 0..hashtags {
@@ -151,3 +459,103 @@ fn display_progress(iter: u16) {
     }
     println!("]");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(TrainConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_values() {
+        let invalid: Vec<TrainConfig> = vec![
+            TrainConfig { sessions: 0, ..Default::default() },
+            TrainConfig { gamma: 1.5, ..Default::default() },
+            TrainConfig { learning_rate: 0., ..Default::default() },
+            TrainConfig { learning_rate: f32::NAN, ..Default::default() },
+            TrainConfig { batch_size: 0, ..Default::default() },
+            TrainConfig { sampling_frequency: 0, ..Default::default() },
+            TrainConfig { target_update_frequency: 0, ..Default::default() },
+            TrainConfig { initial_epsilon: -0.1, ..Default::default() },
+            TrainConfig { epsilon_decay: 0., ..Default::default() },
+            TrainConfig { demonstration_fraction: 2., ..Default::default() },
+            TrainConfig { margin: -1., ..Default::default() },
+            TrainConfig {
+                recording: RecordingConfig {
+                    width: 0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?} was accepted", config);
+        }
+    }
+
+    // Runs in builds without the graphics feature too, where nothing can
+    // open a window.
+    #[test]
+    fn trains_headless() {
+        let dataset_path: PathBuf = std::env::temp_dir().join(format!("train_headless_{}.bin", std::process::id()));
+        let mut game = crate::game::Game::with_seed(SEED);
+        game.max_steps = 50;
+        let mut agent = model::Model::new();
+        agent.add_layer(game.observation_space, 16, true);
+        agent.add_layer(16, game.action_space, false);
+        let config = TrainConfig {
+            sessions: 3,
+            dataset_path: dataset_path.clone(),
+            ..Default::default()
+        };
+        let scores: Vec<f32> = train(&mut game, &mut agent, &config);
+        let saved: usize = load_transitions(&dataset_path).unwrap().len();
+        fs::remove_file(&dataset_path).unwrap();
+        assert_eq!(scores.len(), 3);
+        assert!(saved > 0);
+        assert!(scores.iter().all(|score| score.is_finite()));
+    }
+
+    #[test]
+    fn replay_buffer_is_saved_periodically_and_after_the_last_session() {
+        let config = TrainConfig {
+            sessions: 7,
+            save_every: 3,
+            ..Default::default()
+        };
+        let due: Vec<u16> = (1..=7).filter(|&session| config.is_save_due(session)).collect();
+        assert_eq!(due, vec![3, 6, 7]);
+        let never = TrainConfig {
+            save_every: 0,
+            ..config
+        };
+        assert!(!(1..=7).any(|session| never.is_save_due(session)));
+    }
+
+    #[test]
+    fn failed_replay_buffer_saves_do_not_stop_training() {
+        let mut game = crate::game::Game::with_seed(SEED);
+        game.max_steps = 5;
+        let mut agent = model::Model::new();
+        agent.add_layer(game.observation_space, game.action_space, false);
+        let config = TrainConfig {
+            sessions: 2,
+            save_every: 1,
+            dataset_path: std::env::temp_dir().join("missing_directory").join("transitions.bin"),
+            ..Default::default()
+        };
+        assert_eq!(train(&mut game, &mut agent, &config).len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid training config")]
+    fn train_panics_on_invalid_config() {
+        let mut game = crate::game::Game::with_seed(SEED);
+        let mut agent = model::Model::new();
+        agent.add_layer(game.observation_space, game.action_space, false);
+        train(&mut game, &mut agent, &TrainConfig { gamma: -1., ..Default::default() });
+    }
+}
@@ -0,0 +1,358 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use ndarray::Array1;
+
+use crate::game::Game;
+
+// One episode per file, enough to re-simulate it: the episode seed the start
+// state was drawn from and every action taken.
+//
+// Header: magic, version, action dimensions (u32, 0 for discrete actions),
+// observation dimensions (u32), episode seed (u64), config hash (u64). Then
+// one record per step until the end of the file: the action (u32, or f32 per
+// dimension), the reward (f32) and the observation the action was chosen on
+// (f32 each). Everything little endian.
+const MAGIC: &[u8; 4] = b"RLTJ";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Discrete(usize),
+    Continuous(Array1<f32>),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Discrete(action) => write!(f, "{}", action),
+            Action::Continuous(action) => write!(f, "{}", action),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub action: Action,
+    pub reward: f32,
+    pub observation: Array1<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trajectory {
+    // see Game::restart
+    pub seed: u64,
+    // EnvironmentConfig::config_hash of the game that played the episode
+    pub config_hash: u64,
+    pub steps: Vec<Step>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl Trajectory {
+    pub fn new(seed: u64, config_hash: u64) -> Self {
+        Self {
+            seed,
+            config_hash,
+            steps: vec![],
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write(File::create(path)?)
+    }
+
+    fn write(&self, file: File) -> io::Result<()> {
+        let action_dims: usize = match self.steps.first().map(|step| &step.action) {
+            Some(Action::Continuous(action)) => action.len(),
+            _ => 0,
+        };
+        let observation_dims: usize = self.steps.first().map_or(0, |step| step.observation.len());
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(action_dims as u32).to_le_bytes())?;
+        writer.write_all(&(observation_dims as u32).to_le_bytes())?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&self.config_hash.to_le_bytes())?;
+        for step in &self.steps {
+            match &step.action {
+                Action::Discrete(action) => {
+                    assert_eq!(action_dims, 0, "Discrete and continuous actions in one episode");
+                    writer.write_all(&(*action as u32).to_le_bytes())?;
+                }
+                Action::Continuous(action) => {
+                    assert_eq!(action.len(), action_dims, "Action size changed within the episode");
+                    for value in action {
+                        writer.write_all(&value.to_le_bytes())?;
+                    }
+                }
+            }
+            writer.write_all(&step.reward.to_le_bytes())?;
+            assert_eq!(step.observation.len(), observation_dims, "Observation size changed within the episode");
+            for value in &step.observation {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header: [u8; 29] = [0; 29];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("Not a trajectory file"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("Unsupported trajectory file version"));
+        }
+        let action_dims: usize = u32::from_le_bytes(header[5..9].try_into().unwrap()) as usize;
+        let observation_dims: usize = u32::from_le_bytes(header[9..13].try_into().unwrap()) as usize;
+        let mut trajectory = Self::new(
+            u64::from_le_bytes(header[13..21].try_into().unwrap()),
+            u64::from_le_bytes(header[21..29].try_into().unwrap()),
+        );
+        let action_size: usize = 4 * action_dims.max(1);
+        let mut record: Vec<u8> = vec![0; action_size + 4 + 4 * observation_dims];
+        loop {
+            // a clean end of file can only fall between records
+            let mut filled: usize = 0;
+            while filled < record.len() {
+                match reader.read(&mut record[filled..])? {
+                    0 if filled == 0 => return Ok(trajectory),
+                    0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated trajectory step")),
+                    read => filled += read,
+                }
+            }
+            let float = |offset: usize| f32::from_le_bytes(record[offset..offset + 4].try_into().unwrap());
+            let action: Action = if action_dims == 0 {
+                Action::Discrete(u32::from_le_bytes(record[..4].try_into().unwrap()) as usize)
+            } else {
+                Action::Continuous((0..action_dims).map(|i| float(4 * i)).collect())
+            };
+            trajectory.steps.push(Step {
+                action,
+                reward: float(action_size),
+                observation: (0..observation_dims).map(|i| float(action_size + 4 + 4 * i)).collect(),
+            });
+        }
+    }
+}
+
+// Writes every finished episode of a game to its own file in directory,
+// never replacing an existing one. Episodes cut short by a reset are dropped.
+#[derive(Clone, Debug)]
+pub struct TrajectoryLog {
+    pub directory: PathBuf,
+    // tells the files of runs and parallel games apart
+    pub prefix: String,
+    pub config_hash: u64,
+    episodes: usize,
+    current: Option<Trajectory>,
+}
+
+impl TrajectoryLog {
+    pub fn new(directory: impl Into<PathBuf>, prefix: &str, config_hash: u64) -> Self {
+        Self {
+            directory: directory.into(),
+            prefix: prefix.to_string(),
+            config_hash,
+            episodes: 0,
+            current: None,
+        }
+    }
+
+    pub(crate) fn begin(&mut self, seed: u64) {
+        self.current = Some(Trajectory::new(seed, self.config_hash));
+    }
+
+    pub(crate) fn push(&mut self, step: Step) {
+        if let Some(trajectory) = &mut self.current {
+            trajectory.steps.push(step);
+        }
+    }
+
+    // Writes the episode to the first free episode number.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        let Some(trajectory) = self.current.take() else {
+            return Ok(());
+        };
+        fs::create_dir_all(&self.directory)?;
+        loop {
+            let path: PathBuf = self
+                .directory
+                .join(format!("{}_episode_{}.traj", self.prefix, self.episodes));
+            self.episodes += 1;
+            match File::create_new(&path) {
+                Ok(file) => return trajectory.write(file),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+// Compares what a replay produced with what was recorded, bit for bit so
+// that even -0.0 against 0.0 or differently rounded values count.
+pub(crate) fn check_step(index: usize, recorded: &Step, observation: &Array1<f32>, reward: Option<f32>) -> Result<(), String> {
+    if observation.len() != recorded.observation.len() {
+        return Err(format!(
+            "Step {}: observation has {} values, the trajectory {}",
+            index,
+            observation.len(),
+            recorded.observation.len()
+        ));
+    }
+    for (i, (replayed, original)) in observation.iter().zip(&recorded.observation).enumerate() {
+        if replayed.to_bits() != original.to_bits() {
+            return Err(format!(
+                "Step {}: observation value {} is {}, the trajectory has {}",
+                index, i, replayed, original
+            ));
+        }
+    }
+    if let Some(reward) = reward
+        && reward.to_bits() != recorded.reward.to_bits()
+    {
+        return Err(format!(
+            "Step {}: reward is {}, the trajectory has {}",
+            index, reward, recorded.reward
+        ));
+    }
+    Ok(())
+}
+
+// Re-simulates the episode on game from the trajectory's seed and actions,
+// without a window. Returns the rewards, or where the replay diverged. The
+// game must be configured like the one that recorded it, see config_hash.
+pub fn replay(game: &mut Game, trajectory: &Trajectory) -> Result<Vec<f32>, String> {
+    game.restart(trajectory.seed);
+    let mut observation: Array1<f32> = Array1::zeros(game.observation_space);
+    let mut rewards: Vec<f32> = vec![];
+    for (index, recorded) in trajectory.steps.iter().enumerate() {
+        game.observe(&mut observation);
+        check_step(index, recorded, &observation, None)?;
+        let (reward, finished) = game.apply(&recorded.action);
+        check_step(index, recorded, &observation, Some(reward))?;
+        rewards.push(reward);
+        if finished != (index + 1 == trajectory.steps.len()) {
+            return Err(format!(
+                "Step {}: the episode {} here, unlike in the trajectory",
+                index,
+                if finished { "ends" } else { "continues" }
+            ));
+        }
+    }
+    Ok(rewards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::EnvironmentConfig;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory: PathBuf = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn logged_files(directory: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn logged_episodes_replay_cleanly() {
+        let directory: PathBuf = temp_dir("trajectory_replay");
+        let environment = EnvironmentConfig {
+            max_steps: 40,
+            seed: Some(3),
+            trajectory_dir: Some(directory.clone()),
+            ..Default::default()
+        };
+        let mut game: Game = environment.build(0);
+        let mut played: Vec<Vec<f32>> = vec![vec![], vec![]];
+        for step in 0.. {
+            let (reward, finished) = game.step(step % 2);
+            played[0].push(reward);
+            if finished {
+                break;
+            }
+        }
+        game.reset();
+        for step in 0.. {
+            let (reward, finished) = game.step_continuous(&Array1::from(vec![(step as f32 * 0.3).sin()]));
+            played[1].push(reward);
+            if finished {
+                break;
+            }
+        }
+        assert!(game.trajectory_error().is_none());
+        let paths: Vec<PathBuf> = logged_files(&directory);
+        assert_eq!(paths.len(), 2);
+        for (path, rewards) in paths.iter().zip(&played) {
+            let trajectory: Trajectory = Trajectory::load(path).unwrap();
+            assert_eq!(trajectory.config_hash, environment.config_hash());
+            let mut fresh: Game = EnvironmentConfig {
+                trajectory_dir: None,
+                ..environment.clone()
+            }
+            .build(0);
+            assert_eq!(replay(&mut fresh, &trajectory).as_ref(), Ok(rewards));
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn logs_never_replace_earlier_episodes() {
+        let directory: PathBuf = temp_dir("trajectory_collision");
+        for seed in 0..2 {
+            // a new log per run, restarting its episode count
+            let mut log = TrajectoryLog::new(&directory, "env_0", 0);
+            log.begin(seed);
+            log.push(Step {
+                action: Action::Discrete(0),
+                reward: 0.,
+                observation: Array1::zeros(2),
+            });
+            log.finish().unwrap();
+        }
+        let seeds: Vec<u64> = logged_files(&directory)
+            .iter()
+            .map(|path| Trajectory::load(path).unwrap().seed)
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(seeds, vec![0, 1]);
+    }
+
+    #[test]
+    fn failed_writes_stop_logging_without_failing_the_step() {
+        // a file where the log directory should be
+        let blocked: PathBuf = temp_dir("trajectory_blocked");
+        fs::write(&blocked, b"").unwrap();
+        let mut game: Game = EnvironmentConfig {
+            max_steps: 5,
+            seed: Some(3),
+            trajectory_dir: Some(blocked.clone()),
+            ..Default::default()
+        }
+        .build(0);
+        for _ in 0..10 {
+            if game.step(0).1 {
+                game.reset();
+            }
+        }
+        fs::remove_file(&blocked).unwrap();
+        assert!(game.trajectory_error().is_some());
+    }
+}
@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use ndarray::Array1;
+
+use crate::train::Experience;
+
+// Binary transition files, written and read one record at a time so large
+// replay buffers never need a second copy in memory.
+//
+// Header: magic, version, flags, state dimensions (u32). Then fixed size
+// records until the end of the file: demonstration flag (u8), action (u32),
+// reward (f32), done (u8), state and next state (f32 each) and, when the
+// header's priority flag is set, a priority (f32). Everything little endian.
+const MAGIC: &[u8; 4] = b"RLTR";
+const VERSION: u8 = 1;
+const PRIORITIES_FLAG: u8 = 1;
+
+pub(crate) struct Record {
+    pub(crate) experience: Experience,
+    // belongs in the replay buffer's reserved demonstration part
+    pub(crate) demonstration: bool,
+    pub(crate) priority: Option<f32>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub(crate) struct TransitionWriter {
+    writer: BufWriter<File>,
+    state_dims: usize,
+    priorities: bool,
+}
+
+impl TransitionWriter {
+    pub(crate) fn create(path: impl AsRef<Path>, state_dims: usize, priorities: bool) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, if priorities { PRIORITIES_FLAG } else { 0 }])?;
+        writer.write_all(&(state_dims as u32).to_le_bytes())?;
+        Ok(Self {
+            writer,
+            state_dims,
+            priorities,
+        })
+    }
+
+    pub(crate) fn write(&mut self, experience: &Experience, demonstration: bool, priority: Option<f32>) -> io::Result<()> {
+        assert_eq!(experience.state.len(), self.state_dims, "State size differs from the file's");
+        assert_eq!(experience.next_state.len(), self.state_dims, "State size differs from the file's");
+        assert_eq!(priority.is_some(), self.priorities, "Priorities must be given for all or no records");
+        self.writer.write_all(&[demonstration as u8])?;
+        self.writer.write_all(&(experience.action as u32).to_le_bytes())?;
+        self.writer.write_all(&experience.reward.to_le_bytes())?;
+        self.writer.write_all(&[experience.done as u8])?;
+        for value in experience.state.iter().chain(experience.next_state.iter()) {
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        if let Some(priority) = priority {
+            self.writer.write_all(&priority.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub(crate) struct TransitionReader {
+    reader: BufReader<File>,
+    pub(crate) state_dims: usize,
+    pub(crate) priorities: bool,
+    record: Vec<u8>,
+}
+
+impl TransitionReader {
+    pub(crate) fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header: [u8; 10] = [0; 10];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("Not a transition file"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("Unsupported transition file version"));
+        }
+        let priorities: bool = header[5] & PRIORITIES_FLAG != 0;
+        let state_dims: usize = u32::from_le_bytes(header[6..10].try_into().unwrap()) as usize;
+        let record_size: usize = 1 + 4 + 4 + 1 + 8 * state_dims + if priorities { 4 } else { 0 };
+        Ok(Self {
+            reader,
+            state_dims,
+            priorities,
+            record: vec![0; record_size],
+        })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        // a clean end of file can only fall between records
+        let mut filled: usize = 0;
+        while filled < self.record.len() {
+            match self.reader.read(&mut self.record[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated transition record")),
+                read => filled += read,
+            }
+        }
+        let bytes: &[u8] = &self.record;
+        let float = |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let states: usize = 10;
+        let state: Array1<f32> = (0..self.state_dims).map(|i| float(states + 4 * i)).collect();
+        let next_state: Array1<f32> = (0..self.state_dims)
+            .map(|i| float(states + 4 * (self.state_dims + i)))
+            .collect();
+        Ok(Some(Record {
+            experience: Experience {
+                state,
+                action: u32::from_le_bytes(bytes[1..5].try_into().unwrap()) as usize,
+                reward: float(5),
+                next_state,
+                done: bytes[9] != 0,
+            },
+            demonstration: bytes[0] != 0,
+            priority: self.priorities.then(|| float(states + 8 * self.state_dims)),
+        }))
+    }
+}
+
+impl Iterator for TransitionReader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temporary(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("transitions_{}_{}.bin", name, std::process::id()))
+    }
+
+    fn experience(i: usize) -> Experience {
+        Experience {
+            state: Array1::from(vec![i as f32, -0.5]),
+            action: i % 3,
+            reward: i as f32 * 0.25 - 1.,
+            next_state: Array1::from(vec![i as f32 + 1., 0.5]),
+            done: i.is_multiple_of(2),
+        }
+    }
+
+    #[test]
+    fn records_read_back_as_written() {
+        let path: PathBuf = temporary("round_trip");
+        let mut writer: TransitionWriter = TransitionWriter::create(&path, 2, true).unwrap();
+        for i in 0..5 {
+            writer.write(&experience(i), i < 2, Some(i as f32 + 0.5)).unwrap();
+        }
+        writer.finish().unwrap();
+        let reader: TransitionReader = TransitionReader::open(&path).unwrap();
+        assert_eq!((reader.state_dims, reader.priorities), (2, true));
+        let records: Vec<Record> = reader.collect::<io::Result<_>>().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 5);
+        for (i, record) in records.iter().enumerate() {
+            let expected: Experience = experience(i);
+            assert_eq!(record.experience.state, expected.state);
+            assert_eq!(record.experience.action, expected.action);
+            assert_eq!(record.experience.reward, expected.reward);
+            assert_eq!(record.experience.next_state, expected.next_state);
+            assert_eq!(record.experience.done, expected.done);
+            assert_eq!(record.demonstration, i < 2);
+            assert_eq!(record.priority, Some(i as f32 + 0.5));
+        }
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let path: PathBuf = temporary("bad_magic");
+        fs::write(&path, b"RLTX\x01\x00\x02\x00\x00\x00").unwrap();
+        let error: io::Error = TransitionReader::open(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_records_are_rejected() {
+        let path: PathBuf = temporary("truncated");
+        let mut writer: TransitionWriter = TransitionWriter::create(&path, 2, false).unwrap();
+        writer.write(&experience(0), false, None).unwrap();
+        writer.write(&experience(1), false, None).unwrap();
+        writer.finish().unwrap();
+        let length: u64 = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(length - 3).unwrap();
+        let records: Vec<io::Result<Record>> = TransitionReader::open(&path).unwrap().collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].is_ok());
+        assert_eq!(records[1].as_ref().err().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}